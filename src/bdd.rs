@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use crate::{cfg::Cfg, rules};
+use syn::Meta;
+
+/// A node of a reduced ordered binary decision diagram, referenced by its index in
+/// [`Solver::nodes`].
+pub type Bdd = u32;
+
+const FALSE: Bdd = 0;
+const TRUE: Bdd = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Node {
+    var: u32,
+    low: Bdd,
+    high: Bdd,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Op {
+    And,
+    Or,
+}
+
+/// Decides implication and satisfiability of cfg predicates using binary decision diagrams.
+///
+/// Every atom becomes a variable in order of first use. Nodes, operations and translated
+/// predicates are cached, so a solver should be reused for all checks of an expansion.
+pub struct Solver {
+    nodes: Vec<Node>,
+    unique: HashMap<Node, Bdd>,
+    ops: HashMap<(Op, Bdd, Bdd), Bdd>,
+    nots: HashMap<Bdd, Bdd>,
+    vars: Vec<Meta>,
+    var_index: HashMap<Meta, u32>,
+    cfgs: HashMap<Cfg, Bdd>,
+    // the known relationships between all variables, invalidated when a variable is added
+    rules: Option<Bdd>,
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        let terminal = Node {
+            var: u32::MAX,
+            low: FALSE,
+            high: FALSE,
+        };
+        Self {
+            nodes: vec![terminal, terminal],
+            unique: HashMap::new(),
+            ops: HashMap::new(),
+            nots: HashMap::new(),
+            vars: Vec::new(),
+            var_index: HashMap::new(),
+            cfgs: HashMap::new(),
+            rules: None,
+        }
+    }
+}
+
+impl Solver {
+    /// Returns whether `b` holds on every target on which `a` holds.
+    pub fn implies(&mut self, a: &Cfg, b: &Cfg) -> bool {
+        if a == b {
+            return true;
+        }
+        let a = self.bdd(a);
+        let b = self.bdd(b);
+        let not_b = self.not(b);
+        let counterexample = self.and(a, not_b);
+        !self.is_possible(counterexample)
+    }
+
+    /// Returns whether `cfg` holds on any target.
+    pub fn is_satisfiable(&mut self, cfg: &Cfg) -> bool {
+        let cfg = self.bdd(cfg);
+        self.is_possible(cfg)
+    }
+
+    /// Returns whether `bdd` holds for an assignment that is possible on a real target.
+    pub fn is_possible(&mut self, bdd: Bdd) -> bool {
+        if bdd == FALSE {
+            return false;
+        }
+        let rules = self.rules();
+        self.and(bdd, rules) != FALSE
+    }
+
+    /// Translates `cfg` into a decision diagram.
+    pub fn bdd(&mut self, cfg: &Cfg) -> Bdd {
+        if let Some(&bdd) = self.cfgs.get(cfg) {
+            return bdd;
+        }
+        let bdd = match cfg {
+            Cfg::Atomic(meta) => {
+                let var = self.var(meta);
+                self.mk(var, FALSE, TRUE)
+            }
+            Cfg::Not(inner) => {
+                let inner = self.bdd(inner);
+                self.not(inner)
+            }
+            Cfg::All(list) => list.iter().fold(TRUE, |acc, cfg| {
+                let cfg = self.bdd(cfg);
+                self.and(acc, cfg)
+            }),
+            Cfg::Any(list) => list.iter().fold(FALSE, |acc, cfg| {
+                let cfg = self.bdd(cfg);
+                self.or(acc, cfg)
+            }),
+        };
+        self.cfgs.insert(cfg.clone(), bdd);
+        bdd
+    }
+
+    /// Returns the number of variables, i.e. the distinct atoms seen so far.
+    pub fn var_count(&self) -> usize {
+        self.vars.len()
+    }
+
+    /// Returns the atoms `bdd` depends on, in the order of their variables.
+    pub fn support(&self, bdd: Bdd) -> Vec<Meta> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut used = vec![false; self.vars.len()];
+        let mut stack = vec![bdd];
+        while let Some(bdd) = stack.pop() {
+            if bdd == FALSE || bdd == TRUE || std::mem::replace(&mut seen[bdd as usize], true) {
+                continue;
+            }
+            let node = self.nodes[bdd as usize];
+            used[node.var as usize] = true;
+            stack.extend([node.low, node.high]);
+        }
+        self.vars
+            .iter()
+            .zip(used)
+            .filter(|(_, used)| *used)
+            .map(|(var, _)| var.clone())
+            .collect()
+    }
+
+    /// Returns the assignments of the first `n` variables for which `bdd` holds, in ascending
+    /// order. Assignments are bitsets with bit `i` holding the value of variable `i`.
+    ///
+    /// `bdd` must not depend on any later variable.
+    pub fn assignments(&self, bdd: Bdd, n: usize) -> Vec<u32> {
+        fn core(solver: &Solver, bdd: Bdd, var: usize, n: usize, value: u32, out: &mut Vec<u32>) {
+            if bdd == FALSE {
+                return;
+            }
+            if var == n {
+                out.push(value);
+                return;
+            }
+            let node = solver.nodes[bdd as usize];
+            let (low, high) = if bdd != TRUE && node.var as usize == var {
+                (node.low, node.high)
+            } else {
+                // doesn't depend on this variable
+                (bdd, bdd)
+            };
+            core(solver, low, var + 1, n, value, out);
+            core(solver, high, var + 1, n, value | 1 << var, out);
+        }
+
+        let mut out = Vec::new();
+        core(self, bdd, 0, n, 0, &mut out);
+        out.sort_unstable();
+        out
+    }
+
+    pub fn and(&mut self, a: Bdd, b: Bdd) -> Bdd {
+        self.apply(Op::And, a, b)
+    }
+
+    pub fn or(&mut self, a: Bdd, b: Bdd) -> Bdd {
+        self.apply(Op::Or, a, b)
+    }
+
+    pub fn not(&mut self, a: Bdd) -> Bdd {
+        match a {
+            FALSE => return TRUE,
+            TRUE => return FALSE,
+            _ => {}
+        }
+        if let Some(&bdd) = self.nots.get(&a) {
+            return bdd;
+        }
+        let Node { var, low, high } = self.nodes[a as usize];
+        let low = self.not(low);
+        let high = self.not(high);
+        let bdd = self.mk(var, low, high);
+        self.nots.insert(a, bdd);
+        bdd
+    }
+
+    fn apply(&mut self, op: Op, a: Bdd, b: Bdd) -> Bdd {
+        match (op, a, b) {
+            (Op::And, FALSE, _) | (Op::And, _, FALSE) => return FALSE,
+            (Op::And, TRUE, x) | (Op::And, x, TRUE) => return x,
+            (Op::Or, TRUE, _) | (Op::Or, _, TRUE) => return TRUE,
+            (Op::Or, FALSE, x) | (Op::Or, x, FALSE) => return x,
+            _ if a == b => return a,
+            _ => {}
+        }
+
+        // both operations are commutative
+        let key = (op, a.min(b), a.max(b));
+        if let Some(&bdd) = self.ops.get(&key) {
+            return bdd;
+        }
+
+        let (na, nb) = (self.nodes[a as usize], self.nodes[b as usize]);
+        let var = na.var.min(nb.var);
+        let (a_low, a_high) = if na.var == var {
+            (na.low, na.high)
+        } else {
+            (a, a)
+        };
+        let (b_low, b_high) = if nb.var == var {
+            (nb.low, nb.high)
+        } else {
+            (b, b)
+        };
+        let low = self.apply(op, a_low, b_low);
+        let high = self.apply(op, a_high, b_high);
+        let bdd = self.mk(var, low, high);
+        self.ops.insert(key, bdd);
+        bdd
+    }
+
+    fn mk(&mut self, var: u32, low: Bdd, high: Bdd) -> Bdd {
+        if low == high {
+            return low;
+        }
+        let node = Node { var, low, high };
+        if let Some(&bdd) = self.unique.get(&node) {
+            return bdd;
+        }
+        let bdd = self.nodes.len() as Bdd;
+        self.nodes.push(node);
+        self.unique.insert(node, bdd);
+        bdd
+    }
+
+    fn var(&mut self, meta: &Meta) -> u32 {
+        if let Some(&var) = self.var_index.get(meta) {
+            return var;
+        }
+        let var = self.vars.len() as u32;
+        self.vars.push(meta.clone());
+        self.var_index.insert(meta.clone(), var);
+        self.rules = None;
+        var
+    }
+
+    /// Returns the relationships between all known variables that hold on every target.
+    pub fn rules(&mut self) -> Bdd {
+        if let Some(rules) = self.rules {
+            return rules;
+        }
+        // the constraints only mention known atoms, so no variable is added here
+        let constraints = rules::constraints(&self.vars);
+        let rules = constraints.iter().fold(TRUE, |acc, cfg| {
+            let cfg = self.bdd(cfg);
+            self.and(acc, cfg)
+        });
+        self.rules = Some(rules);
+        rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(s: &str) -> Cfg {
+        Cfg::Atomic(Box::new(syn::parse_str(s).unwrap()))
+    }
+
+    #[test]
+    fn scales_to_many_atoms() {
+        // a truth table over 64 atoms could never be enumerated
+        let atoms = (0..64).map(|i| atom(&format!("a{i}"))).collect::<Vec<_>>();
+        let all = Cfg::All(atoms.clone());
+        let any = Cfg::Any(atoms.clone());
+
+        let mut solver = Solver::default();
+        assert!(solver.implies(&all, &any));
+        assert!(!solver.implies(&any, &all));
+        for atom in &atoms {
+            assert!(solver.implies(&all, atom));
+            assert!(solver.implies(atom, &any));
+        }
+        let contradiction = Cfg::All(vec![all, Cfg::Not(Box::new(atoms[63].clone()))]);
+        assert!(!solver.is_satisfiable(&contradiction));
+    }
+
+    #[test]
+    fn rules_apply_to_later_atoms() {
+        let mut solver = Solver::default();
+        let unix = atom("unix");
+        assert!(solver.is_satisfiable(&unix));
+        let both = Cfg::All(vec![unix, atom("windows")]);
+        assert!(!solver.is_satisfiable(&both));
+    }
+}
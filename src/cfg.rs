@@ -1,8 +1,17 @@
-use std::collections::{HashMap, HashSet};
+use std::{collections::HashSet, fmt};
 
-use proc_macro2::TokenStream;
-use quote::quote;
-use syn::{Attribute, Meta, MetaList, Token, punctuated::Punctuated};
+use crate::bdd::{Bdd, Solver};
+use proc_macro2::{TokenStream, TokenTree};
+use quote::{ToTokens, quote};
+use syn::{
+    Attribute, Expr, ExprLit, Lit, LitBool, Meta, MetaList, Token,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+};
+
+/// Above this many atoms that a predicate depends on, it is not minimized as its truth table gets
+/// too big.
+const MAX_SIMPLIFY_ATOMS: usize = 12;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Cfg {
@@ -13,155 +22,472 @@ pub enum Cfg {
 }
 
 impl Cfg {
-    // dedup with generate_all_combinations
+    /// Returns whether `other` holds on every target on which `self` holds.
+    ///
+    /// Assignments that contradict the known relationships between target cfgs, like
+    /// `all(unix, windows)`, are never considered. Use a shared [`Solver`] for repeated checks.
     pub fn implies(&self, other: &Cfg) -> bool {
-        // Fast path.
-        if self == other {
-            return true;
-        }
+        Solver::default().implies(self, other)
+    }
 
-        // Collect atoms from both sides.
-        let atoms = {
-            let mut set = HashSet::<Meta>::new();
-            self.collect_atoms(&mut set);
-            other.collect_atoms(&mut set);
-            set.into_iter().collect::<Vec<_>>()
-        };
+    /// Returns whether `self` holds for any possible assignment of its atoms.
+    pub fn is_satisfiable(&self) -> bool {
+        Solver::default().is_satisfiable(self)
+    }
+}
 
-        // Map each atom to an index.
-        let mut idx = HashMap::<Meta, usize>::with_capacity(atoms.len());
-        for (i, a) in atoms.iter().cloned().enumerate() {
-            idx.insert(a, i);
+impl Cfg {
+    /// Returns a minimal equivalent predicate in disjunctive normal form.
+    ///
+    /// Predicates that always or never hold are folded to `all()` and `any()` respectively.
+    /// Assignments that are impossible on a real target are treated as don't-cares, so e.g.
+    /// `all(unix, not(windows))` becomes `unix`. Predicates with too many atoms are returned
+    /// unchanged, unless they are constant.
+    pub fn simplify(&self) -> Cfg {
+        // only the atoms the predicate depends on matter, e.g. the configurations merged into
+        // an expansion often differ in cfgs that don't change it
+        let mut solver = Solver::default();
+        let mut cfg = solver.bdd(self);
+        let atoms = solver.support(cfg);
+        if atoms.len() > MAX_SIMPLIFY_ATOMS {
+            // still fold constants
+            return if !self.is_satisfiable() {
+                Cfg::Any(Vec::new())
+            } else if Cfg::All(Vec::new()).implies(self) {
+                Cfg::All(Vec::new())
+            } else {
+                self.clone()
+            };
         }
 
-        // Enumerate all boolean assignments. If we find a counterexample where
-        // self is true and other is false, implication does not hold.
-        let n = atoms.len();
-        let mut vals = vec![false; n];
-
-        fn dfs(
-            index: usize,
-            len: usize,
-            assignment: &mut [bool],
-            index_map: &HashMap<Meta, usize>,
-            left: &Cfg,
-            right: &Cfg,
-        ) -> bool {
-            if index == len {
-                let left = left.eval_with(assignment, index_map);
-                if !left {
-                    return true; // if left is false, we dont care about right
-                }
-                let right = right.eval_with(assignment, index_map);
-                return right; // must be true whenever left is true
+        // Assignments are bitsets with bit `i` holding the value of `atoms[i]`, read off the
+        // decision diagrams instead of evaluating every row of the truth table. The variables
+        // of the solver have to be exactly the atoms, in order.
+        if atoms.len() < solver.var_count() {
+            solver = Solver::default();
+            for atom in &atoms {
+                solver.bdd(&Cfg::Atomic(Box::new(atom.clone())));
             }
-
-            // Try false
-            assignment[index] = false;
-            if !dfs(index + 1, len, assignment, index_map, left, right) {
-                return false;
-            }
-
-            // Try true
-            assignment[index] = true;
-            if !dfs(index + 1, len, assignment, index_map, left, right) {
-                return false;
-            }
-
-            true
+            // the rules between the atoms, before the others are added
+            let rules = solver.rules();
+            cfg = solver.bdd(self);
+            return Self::minimize(&atoms, &mut solver, cfg, rules);
         }
+        let rules = solver.rules();
+        Self::minimize(&atoms, &mut solver, cfg, rules)
+    }
+
+    /// Minimizes the predicate `cfg` over `atoms`, which are the first variables of `solver`.
+    fn minimize(atoms: &[Meta], solver: &mut Solver, cfg: Bdd, rules: Bdd) -> Cfg {
+        let possible = solver.and(cfg, rules);
+        let impossible = solver.not(rules);
+        let minterms = solver.assignments(possible, atoms.len());
+        let dont_cares = solver.assignments(impossible, atoms.len());
+
+        // impossible assignments may be covered, but don't have to be
+        let primes = prime_implicants(&[minterms.as_slice(), &dont_cares].concat());
+
+        // order the terms by their literals, atoms in order of appearance and positive first
+        let mut implicants = cover(&minterms, primes);
+        implicants.sort_by_key(|&(value, mask)| {
+            (0..atoms.len())
+                .map(|i| match (mask & (1 << i) != 0, value & (1 << i) != 0) {
+                    (false, true) => 0,
+                    (false, false) => 1,
+                    (true, _) => 2,
+                })
+                .collect::<Vec<u8>>()
+        });
+
+        let terms = implicants
+            .into_iter()
+            .map(|(value, mask)| {
+                let literals = atoms
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| mask & (1 << i) == 0)
+                    .map(|(i, atom)| {
+                        let atom = Cfg::Atomic(Box::new(atom.clone()));
+                        if value & (1 << i) != 0 {
+                            atom
+                        } else {
+                            Cfg::Not(Box::new(atom))
+                        }
+                    })
+                    .collect();
+                Cfg::all_of(literals)
+            })
+            .collect();
+        Cfg::any_of(terms)
+    }
 
-        dfs(0, n, &mut vals, &idx, self, other)
+    /// Returns the atoms of the predicate in order of appearance.
+    pub fn atoms(&self) -> Vec<Meta> {
+        let mut atoms = Vec::new();
+        self.collect_ordered_atoms(&mut atoms);
+        atoms
     }
 
-    fn collect_atoms(&self, out: &mut HashSet<Meta>) {
+    fn collect_ordered_atoms(&self, out: &mut Vec<Meta>) {
         match self {
             Cfg::Atomic(meta) => {
-                out.insert((**meta).clone());
+                if !out.contains(meta) {
+                    out.push((**meta).clone());
+                }
             }
-            Cfg::Not(inner) => inner.collect_atoms(out),
+            Cfg::Not(inner) => inner.collect_ordered_atoms(out),
             Cfg::Any(vec) | Cfg::All(vec) => {
                 for inner in vec {
-                    inner.collect_atoms(out);
+                    inner.collect_ordered_atoms(out);
                 }
             }
         }
     }
+}
 
-    fn eval_with(&self, vals: &[bool], index_map: &HashMap<Meta, usize>) -> bool {
-        match self {
-            Cfg::Atomic(meta) => {
-                let index = index_map
-                    .get(&(**meta))
-                    .expect("atom index missing (collect_atoms/idx bug)");
-                vals[*index]
+/// An implicant of a boolean function as `(value, mask)`, where the bits set in `mask` are
+/// don't-cares and the remaining bits have to match `value`.
+type Implicant = (u32, u32);
+
+/// Computes the prime implicants of the function that holds exactly for `minterms`
+/// (Quine–McCluskey).
+///
+/// Don't-cares are passed as part of `minterms`, as they only need to be excluded when
+/// covering.
+fn prime_implicants(minterms: &[u32]) -> Vec<Implicant> {
+    let mut current = minterms
+        .iter()
+        .map(|&m| (m, 0))
+        .collect::<HashSet<Implicant>>();
+    let mut primes = Vec::new();
+    while !current.is_empty() {
+        let mut next = HashSet::new();
+        let mut merged = HashSet::new();
+        for &(value, mask) in &current {
+            // pairs differing in a single bit, visited from the one with the bit unset
+            for bit in (0..u32::BITS).map(|i| 1 << i) {
+                let partner = (value | bit, mask);
+                if (value | mask) & bit == 0 && current.contains(&partner) {
+                    next.insert((value, mask | bit));
+                    merged.insert((value, mask));
+                    merged.insert(partner);
+                }
             }
-            Cfg::Not(inner) => !inner.eval_with(vals, index_map),
-            Cfg::All(vec) => vec.iter().all(|inner| inner.eval_with(vals, index_map)),
-            Cfg::Any(vec) => vec.iter().any(|inner| inner.eval_with(vals, index_map)),
         }
+        primes.extend(
+            current
+                .iter()
+                .filter(|implicant| !merged.contains(implicant)),
+        );
+        current = next;
     }
+    // the sets are unordered, but the cover picked from the primes has to be deterministic
+    primes.sort_unstable();
+    primes
+}
+
+/// Selects prime implicants covering all `minterms`, essential ones first and then greedily.
+fn cover(minterms: &[u32], primes: Vec<Implicant>) -> Vec<Implicant> {
+    let covers = |(value, mask): Implicant, minterm: u32| minterm & !mask == value;
+
+    let mut chosen = Vec::<Implicant>::new();
+    for &minterm in minterms {
+        let mut covering = primes.iter().filter(|&&p| covers(p, minterm));
+        if let (Some(&prime), None) = (covering.next(), covering.next())
+            && !chosen.contains(&prime)
+        {
+            chosen.push(prime);
+        }
+    }
+
+    let mut remaining = minterms
+        .iter()
+        .copied()
+        .filter(|&m| !chosen.iter().any(|&p| covers(p, m)))
+        .collect::<Vec<_>>();
+    while !remaining.is_empty() {
+        // prefer the prime covering the most minterms, then the one with the fewest literals
+        let best = *primes
+            .iter()
+            .max_by_key(|&&p| {
+                let covered = remaining.iter().filter(|&&m| covers(p, m)).count();
+                (covered, p.1.count_ones())
+            })
+            .unwrap();
+        remaining.retain(|&m| !covers(best, m));
+        chosen.push(best);
+    }
+    chosen
 }
 
 impl Cfg {
-    pub fn from_attr(attr: &Attribute) -> Option<Cfg> {
-        if !attr.path().is_ident("cfg") {
-            return None;
+    /// Combines `cfgs` into their conjunction, without wrapping a single cfg in `all(...)`.
+    pub fn all_of(mut cfgs: Vec<Cfg>) -> Cfg {
+        if cfgs.len() == 1 {
+            cfgs.pop().unwrap()
+        } else {
+            Cfg::All(cfgs)
+        }
+    }
+
+    /// Combines `cfgs` into their disjunction, without wrapping a single cfg in `any(...)`.
+    pub fn any_of(mut cfgs: Vec<Cfg>) -> Cfg {
+        if cfgs.len() == 1 {
+            cfgs.pop().unwrap()
+        } else {
+            Cfg::Any(cfgs)
+        }
+    }
+
+    /// Computes the effective predicate of each branch of an if / else-if / else chain.
+    ///
+    /// A branch is taken if its own predicate holds and none of the earlier ones do.
+    pub fn first_match(branches: &[Cfg]) -> Vec<Cfg> {
+        branches
+            .iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let mut list = Vec::with_capacity(i + 1);
+                if *cfg != Cfg::All(Vec::new()) {
+                    list.push(cfg.clone());
+                }
+                list.extend(branches[..i].iter().cloned().map(|c| Cfg::Not(Box::new(c))));
+                Cfg::all_of(list)
+            })
+            .collect()
+    }
+
+    pub fn from_attr(attr: &Attribute) -> syn::Result<Option<Cfg>> {
+        Self::from_attr_named(attr, "cfg")
+    }
+
+    /// Parses an attribute that takes a single cfg predicate, like `#[name(pred)]`.
+    ///
+    /// Returns `None` if the attribute has a different name and an error if the predicate is
+    /// malformed.
+    pub fn from_attr_named(attr: &Attribute, name: &str) -> syn::Result<Option<Cfg>> {
+        if !attr.path().is_ident(name) {
+            return Ok(None);
         }
 
         let Meta::List(list) = &attr.meta else {
-            return None;
+            return Err(syn::Error::new_spanned(
+                attr,
+                format!("expected `#[{name}(predicate)]`"),
+            ));
         };
 
-        // cfg(...) must contain exactly one meta item; cfg(a, b) => ignore
-        let items: Punctuated<Meta, Token![,]> = list
-            .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
-            .ok()?;
+        // cfg(...) must contain exactly one predicate
+        let items = parse_predicates(list)?;
+        let pred = single_predicate(list, items, "use `all(...)` or `any(...)` to combine them")?;
+        Self::from_predicate(pred).map(Some)
+    }
 
-        if items.len() != 1 {
-            return None;
+    /// Parses `#[cfg_attr(pred, attrs...)]` into its predicate and the attributes it expands to.
+    pub fn from_cfg_attr(attr: &Attribute) -> syn::Result<Option<(Cfg, TokenStream)>> {
+        if !attr.path().is_ident("cfg_attr") {
+            return Ok(None);
         }
 
-        Self::from_cfg_meta(items.into_iter().next().unwrap())
+        let Meta::List(list) = &attr.meta else {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "expected `#[cfg_attr(predicate, attrs...)]`",
+            ));
+        };
+
+        let (pred, items) = list.parse_args_with(|input: ParseStream| {
+            let pred = input.parse::<Predicate>()?;
+            if input.is_empty() {
+                return Ok((pred, Punctuated::new()));
+            }
+            input.parse::<Token![,]>()?;
+            let items = Punctuated::<Meta, Token![,]>::parse_terminated(input)?;
+            Ok((pred, items))
+        })?;
+        let cfg = Self::from_predicate(pred)?;
+
+        let mut attrs = TokenStream::new();
+        for meta in items {
+            let attr = Attribute {
+                meta,
+                ..attr.clone()
+            };
+            attr.to_tokens(&mut attrs);
+        }
+        Ok(Some((cfg, attrs)))
+    }
+
+    fn from_predicate(pred: Predicate) -> syn::Result<Cfg> {
+        match pred {
+            Predicate::Bool(lit) if lit.value => Ok(Cfg::All(Vec::new())),
+            Predicate::Bool(_) => Ok(Cfg::Any(Vec::new())),
+            Predicate::Meta(meta) => Self::from_cfg_meta(*meta),
+        }
     }
 
-    fn from_cfg_meta(meta: Meta) -> Option<Cfg> {
+    fn from_cfg_meta(meta: Meta) -> syn::Result<Cfg> {
         match meta {
             Meta::List(list) if list.path.is_ident("any") => {
-                let args = Self::from_cfg_args(list)?;
-                Some(Cfg::Any(args))
+                let args = Self::from_cfg_args(&list)?;
+                Ok(Cfg::Any(args))
             }
             Meta::List(list) if list.path.is_ident("all") => {
-                let args = Self::from_cfg_args(list)?;
-                Some(Cfg::All(args))
+                let args = Self::from_cfg_args(&list)?;
+                Ok(Cfg::All(args))
             }
             Meta::List(list) if list.path.is_ident("not") => {
-                let args = Self::from_cfg_args(list)?;
-                if args.len() != 1 {
-                    return None; // not() or not(a,b) => ignore
-                }
-                Some(Cfg::Not(Box::new(args.into_iter().next().unwrap())))
+                let items = parse_predicates(&list)?;
+                let pred = single_predicate(
+                    &list,
+                    items,
+                    "use `not(any(...))` or `not(all(...))` to negate several",
+                )?;
+                Ok(Cfg::Not(Box::new(Self::from_predicate(pred)?)))
+            }
+            Meta::Path(path) if path.get_ident().is_some() => {
+                Ok(Cfg::Atomic(Box::new(Meta::Path(path))))
             }
-            other => Some(Cfg::Atomic(Box::new(other))),
+            Meta::NameValue(nv)
+                if nv.path.get_ident().is_some()
+                    && matches!(
+                        &nv.value,
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Str(_),
+                            ..
+                        })
+                    ) =>
+            {
+                Ok(Cfg::Atomic(Box::new(Meta::NameValue(nv))))
+            }
+            other => Err(syn::Error::new_spanned(
+                other,
+                "unsupported cfg predicate, expected `name`, `name = \"value\"`, `all(...)`, \
+                 `any(...)`, `not(...)`, `true` or `false`",
+            )),
+        }
+    }
+
+    fn from_cfg_args(list: &MetaList) -> syn::Result<Vec<Cfg>> {
+        parse_predicates(list)?
+            .into_iter()
+            .map(Self::from_predicate)
+            .collect()
+    }
+}
+
+/// Parses the comma separated predicates in `list`.
+fn parse_predicates(list: &MetaList) -> syn::Result<Punctuated<Predicate, Token![,]>> {
+    list.parse_args_with(Punctuated::<Predicate, Token![,]>::parse_terminated)
+        .map_err(|err| syn::Error::new(err.span(), format!("invalid cfg predicate: {err}")))
+}
+
+/// Returns the only predicate in `items`, or an error pointing at the missing or extra ones.
+fn single_predicate(
+    list: &MetaList,
+    items: Punctuated<Predicate, Token![,]>,
+    hint: &str,
+) -> syn::Result<Predicate> {
+    let name = list.path.to_token_stream();
+    match items.len() {
+        0 => Err(syn::Error::new_spanned(
+            list,
+            format!("expected a cfg predicate in `{name}(...)`"),
+        )),
+        1 => Ok(items.into_iter().next().unwrap()),
+        _ => Err(syn::Error::new_spanned(
+            &items[1],
+            format!("expected a single cfg predicate in `{name}(...)`, {hint}"),
+        )),
+    }
+}
+
+/// A single cfg predicate, which unlike a `Meta` may also be a `true` or `false` literal.
+enum Predicate {
+    Bool(LitBool),
+    Meta(Box<Meta>),
+}
+
+impl Parse for Predicate {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(LitBool) {
+            input.parse().map(Predicate::Bool)
+        } else {
+            input.parse().map(|meta| Predicate::Meta(Box::new(meta)))
+        }
+    }
+}
+
+impl ToTokens for Predicate {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        match self {
+            Predicate::Bool(lit) => lit.to_tokens(tokens),
+            Predicate::Meta(meta) => meta.to_tokens(tokens),
         }
     }
+}
 
-    fn from_cfg_args(list: MetaList) -> Option<Vec<Cfg>> {
-        let nested: Punctuated<Meta, Token![,]> = list
-            .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
-            .ok()?;
+/// Parses a single predicate, as it is written inside of `#[cfg(...)]`.
+impl Parse for Cfg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        Cfg::from_predicate(input.parse()?)
+    }
+}
 
-        nested.into_iter().map(Self::from_cfg_meta).collect()
+/// The arms of a `#[cfg_match] { pred => tokens, ..., _ => tokens }` block.
+///
+/// Each arm carries its effective predicate, so at most one arm applies in any configuration.
+pub struct CfgMatch(pub Vec<(Cfg, TokenStream)>);
+
+impl Parse for CfgMatch {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut cfgs = Vec::new();
+        let mut bodies = Vec::new();
+        while !input.is_empty() {
+            // pred => or _ =>
+            let cfg = if input.peek(Token![_]) {
+                input.parse::<Token![_]>()?;
+                Cfg::All(Vec::new())
+            } else {
+                let pred = input.parse().map_err(|err| {
+                    syn::Error::new(err.span(), "expected a cfg predicate or `_`")
+                })?;
+                Cfg::from_predicate(pred)?
+            };
+            input.parse::<Token![=>]>()?;
+
+            // the tokens of the arm, without the delimiters of a group
+            let body = match input.parse::<TokenTree>()? {
+                TokenTree::Group(g) => g.stream(),
+                tt => tt.into(),
+            };
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+
+            cfgs.push(cfg);
+            bodies.push(body);
+        }
+
+        Ok(Self(
+            Cfg::first_match(&cfgs).into_iter().zip(bodies).collect(),
+        ))
     }
 }
 
 impl Cfg {
     pub fn to_token_stream(&self) -> TokenStream {
+        // all() always holds, so the attribute can be omitted
+        if matches!(self, Cfg::All(list) if list.is_empty()) {
+            return TokenStream::new();
+        }
+
         let pred = self.to_cfg_meta();
         quote!(#[cfg(#pred)])
     }
 
-    fn to_cfg_meta(&self) -> TokenStream {
+    /// The predicate as it is written inside of `#[cfg(...)]`.
+    pub fn to_cfg_meta(&self) -> TokenStream {
         match self {
             Cfg::Any(xs) => {
                 let inner = xs.iter().map(|c| c.to_cfg_meta());
@@ -180,6 +506,26 @@ impl Cfg {
     }
 }
 
+/// Formats the predicate like it is written in a `#[cfg(...)]`, for diagnostics.
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, list) = match self {
+            Cfg::Any(list) => ("any", list),
+            Cfg::All(list) => ("all", list),
+            Cfg::Not(inner) => return write!(f, "not({inner})"),
+            Cfg::Atomic(meta) => return write!(f, "{}", meta.to_token_stream()),
+        };
+        write!(f, "{name}(")?;
+        for (i, cfg) in list.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{cfg}")?;
+        }
+        write!(f, ")")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -303,6 +649,176 @@ mod tests {
         assert!(y2.implies(&y1));
     }
 
+    #[test]
+    fn contradiction_is_not_satisfiable() {
+        let a = atom("a");
+        let b = atom("b");
+
+        assert!(all(vec![a.clone(), b.clone()]).is_satisfiable());
+        assert!(!all(vec![a.clone(), all(vec![not(a), b])]).is_satisfiable());
+        assert!(!any(vec![]).is_satisfiable());
+        assert!(all(vec![]).is_satisfiable());
+    }
+
+    #[test]
+    fn first_match_excludes_earlier_branches() {
+        let a = atom("a");
+        let b = atom("b");
+        let branches = Cfg::first_match(&[a.clone(), b.clone(), all(vec![])]);
+
+        assert_eq!(branches[0], a);
+        assert!(branches[1].implies(&b));
+        assert!(branches[1].implies(&not(a.clone())));
+        assert!(branches[2].implies(&not(any(vec![a, b]))));
+        assert!(branches[0].implies(&not(branches[1].clone())));
+    }
+
+    #[test]
+    fn cfg_match_arms_exclude_each_other() {
+        let arms = syn::parse_str::<CfgMatch>("a => { 1 }, any(b, c) => 2, _ => (3)").unwrap();
+        let cfgs = arms
+            .0
+            .iter()
+            .map(|(cfg, _)| cfg.clone())
+            .collect::<Vec<_>>();
+        let bodies = arms
+            .0
+            .iter()
+            .map(|(_, body)| body.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(bodies, ["1", "2", "3"]);
+        assert_eq!(cfgs[0], atom("a"));
+        assert!(cfgs[1].implies(&not(atom("a"))));
+        assert!(cfgs[2].implies(&all(vec![not(atom("a")), not(atom("b")), not(atom("c"))])));
+    }
+
+    #[test]
+    fn simplify_drops_irrelevant_atoms() {
+        let a = atom("a");
+        let b = atom("b");
+        let c = atom("c");
+        let cfg = any(vec![
+            all(vec![not(a.clone()), b.clone(), not(c.clone())]),
+            all(vec![a.clone(), b.clone(), not(c.clone())]),
+            all(vec![not(a.clone()), b.clone(), c.clone()]),
+            all(vec![a, b.clone(), c]),
+        ]);
+        assert_eq!(cfg.simplify(), b);
+    }
+
+    #[test]
+    fn simplify_keeps_equivalence() {
+        let a = atom("a");
+        let b = atom("b");
+        let c = atom("c");
+        let cfg = any(vec![
+            all(vec![a.clone(), b.clone()]),
+            all(vec![a.clone(), not(b.clone()), c.clone()]),
+            all(vec![not(a.clone()), b.clone(), c.clone()]),
+        ]);
+        let simplified = cfg.simplify();
+        assert!(cfg.implies(&simplified));
+        assert!(simplified.implies(&cfg));
+        assert_eq!(
+            simplified,
+            any(vec![
+                all(vec![a.clone(), b.clone()]),
+                all(vec![a, c.clone()]),
+                all(vec![b, c]),
+            ])
+        );
+    }
+
+    #[test]
+    fn simplify_folds_constants() {
+        let a = atom("a");
+        assert_eq!(any(vec![a.clone(), not(a.clone())]).simplify(), all(vec![]));
+        assert_eq!(all(vec![a.clone(), not(a)]).simplify(), any(vec![]));
+    }
+
+    #[test]
+    fn simplify_folds_large_constants() {
+        let atoms = (0..20).map(|i| atom(&format!("a{i}"))).collect::<Vec<_>>();
+        let any_atom = any(atoms.clone());
+        assert_eq!(any_atom.simplify(), any_atom);
+        assert_eq!(
+            any(vec![any_atom.clone(), not(any_atom.clone())]).simplify(),
+            all(vec![])
+        );
+        assert_eq!(
+            all(vec![
+                any_atom.clone(),
+                all(atoms.iter().cloned().map(not).collect())
+            ])
+            .simplify(),
+            any(vec![])
+        );
+    }
+
+    #[test]
+    fn simplify_ignores_irrelevant_atoms() {
+        // only `a` matters, even though there are too many atoms for a truth table
+        let mut list = vec![atom("a")];
+        list.extend((0..20).map(|i| {
+            let atom = atom(&format!("x{i}"));
+            any(vec![atom.clone(), not(atom)])
+        }));
+        assert_eq!(all(list).simplify(), atom("a"));
+    }
+
+    #[test]
+    fn bool_literals_are_constants() {
+        let attr: Attribute = syn::parse_quote!(#[cfg(any(false, all(true, a)))]);
+        let cfg = Cfg::from_attr(&attr).unwrap().unwrap();
+        assert_eq!(cfg.simplify(), atom("a"));
+    }
+
+    #[test]
+    fn target_rules_are_respected() {
+        let unix = atom("unix");
+        let windows = atom("windows");
+        let linux = atom("target_os = \"linux\"");
+        let macos = atom("target_os = \"macos\"");
+
+        assert!(linux.implies(&unix));
+        assert!(linux.implies(&atom("target_family = \"unix\"")));
+        assert!(macos.implies(&atom("target_vendor = \"apple\"")));
+        assert!(!unix.implies(&linux));
+        assert!(!all(vec![unix.clone(), windows.clone()]).is_satisfiable());
+        assert!(!all(vec![linux.clone(), macos]).is_satisfiable());
+        assert!(!all(vec![linux, windows.clone()]).is_satisfiable());
+        assert_eq!(all(vec![unix.clone(), not(windows)]).simplify(), unix);
+    }
+
+    #[test]
+    fn malformed_predicates_are_errors() {
+        let attrs: [Attribute; 6] = [
+            syn::parse_quote!(#[cfg(a, b)]),
+            syn::parse_quote!(#[cfg()]),
+            syn::parse_quote!(#[cfg]),
+            syn::parse_quote!(#[cfg(not())]),
+            syn::parse_quote!(#[cfg(all(a, not(b, c)))]),
+            syn::parse_quote!(#[cfg(a::b)]),
+        ];
+        for attr in attrs {
+            assert!(Cfg::from_attr(&attr).is_err());
+        }
+
+        let other: Attribute = syn::parse_quote!(#[derive(Debug)]);
+        assert!(Cfg::from_attr(&other).unwrap().is_none());
+    }
+
+    #[test]
+    fn display_matches_cfg_syntax() {
+        let attr: Attribute = syn::parse_quote!(#[cfg(all(not(windows), feature = "x", any()))]);
+        let cfg = Cfg::from_attr(&attr).unwrap().unwrap();
+        assert_eq!(
+            cfg.to_string(),
+            r#"all(not(windows), feature = "x", any())"#
+        );
+    }
+
     #[test]
     fn de_morgan_equality() {
         // not(any(a, b)) ⇒ and(not(a), not(b))
@@ -1,12 +1,40 @@
-use std::iter;
+use std::{collections::VecDeque, iter};
 
-use crate::cfg::Cfg;
-use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use crate::{
+    cfg::{Cfg, CfgMatch},
+    split::is_container_header,
+};
+use proc_macro2::{Delimiter, Group, Spacing, TokenStream, TokenTree};
+use quote::ToTokens;
 use syn::{
-    Attribute,
+    Attribute, Meta,
     parse::{Parse, ParseStream},
 };
 
+/// Operators made of several punctuation characters, which a cfg applies to as a whole.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "...", "..=", "::", "->", "=>", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "^=", "&=", "|=", "<<", ">>", "..",
+];
+
+/// Keywords that start an item or statement, which rustc lets a `#[cfg]` apply to as a whole.
+///
+/// Visibilities and qualifiers like `pub`, `unsafe` or `extern` are not included, a `#[cfg]` in
+/// front of them applies to just that token. `const`, `static` and `union` are told apart from
+/// other uses by what follows them, see [`is_item_start`].
+const ITEM_KEYWORDS: &[&str] = &[
+    "fn",
+    "struct",
+    "enum",
+    "trait",
+    "impl",
+    "mod",
+    "use",
+    "type",
+    "let",
+    "macro_rules",
+];
+
 pub struct AnyAttribute(Attribute);
 
 impl Parse for AnyAttribute {
@@ -29,44 +57,754 @@ pub fn parse_any_attr(ts: TokenStream) -> syn::Result<Attribute> {
     syn::parse2::<AnyAttribute>(ts).map(|a| a.0)
 }
 
-pub fn find_cfg_attrs(ts: TokenStream) -> Vec<Cfg> {
-    fn core(ts: TokenStream, out: &mut Vec<Cfg>) {
-        let mut it = ts.into_iter().peekable();
+/// An iterator over the token trees of a stream that can look arbitrarily far ahead.
+pub struct TokenIter(VecDeque<TokenTree>);
+
+impl TokenIter {
+    pub fn new(ts: TokenStream) -> Self {
+        Self(ts.into_iter().collect())
+    }
+
+    pub fn peek(&self) -> Option<&TokenTree> {
+        self.0.front()
+    }
+
+    pub fn peek_nth(&self, n: usize) -> Option<&TokenTree> {
+        self.0.get(n)
+    }
+
+    /// Puts `ts` back in front of the remaining tokens.
+    pub fn push_front(&mut self, ts: TokenStream) {
+        let tokens = ts.into_iter().collect::<Vec<_>>();
+        for tt in tokens.into_iter().rev() {
+            self.0.push_front(tt);
+        }
+    }
+}
+
+impl Iterator for TokenIter {
+    type Item = TokenTree;
+
+    fn next(&mut self) -> Option<TokenTree> {
+        self.0.pop_front()
+    }
+}
+
+/// Parses the attribute starting at `pound` if it is followed by a bracket group.
+///
+/// The bracket group is only peeked, not consumed.
+pub fn peek_attr(pound: &TokenTree, it: &TokenIter) -> Option<Attribute> {
+    parse_attr_at(pound, it.peek())
+}
+
+/// Parses the inner `#![...]` attribute at the front of `it` without consuming it.
+pub fn peek_inner_attr(it: &TokenIter) -> Option<Attribute> {
+    let (Some(pound), Some(TokenTree::Punct(bang)), Some(TokenTree::Group(group))) =
+        (it.peek(), it.peek_nth(1), it.peek_nth(2))
+    else {
+        return None;
+    };
+    if bang.as_char() != '!' || group.delimiter() != Delimiter::Bracket {
+        return None;
+    }
+
+    let ts = TokenStream::from_iter([pound.clone(), bang.clone().into(), group.clone().into()]);
+    parse_any_attr(ts).ok()
+}
+
+/// Consumes the `#![cfg(...)]` attributes at the front of `it`, which make the enclosing group
+/// conditional.
+///
+/// Returns the conjunction of their predicates, if there are any.
+pub fn take_inner_cfg(it: &mut TokenIter) -> syn::Result<Option<Cfg>> {
+    let mut cfgs = Vec::new();
+    while let Some(attr) = peek_inner_attr(it)
+        && attr.path().is_ident("cfg")
+    {
+        // consume #![cfg(...)]
+        let _ = it.next();
+        let _ = it.next();
+        let _ = it.next();
+        cfgs.extend(Cfg::from_attr(&attr)?);
+    }
+    Ok((!cfgs.is_empty()).then(|| Cfg::all_of(cfgs)))
+}
+
+/// Parses the attribute at the front of `it` without consuming it.
+pub fn peek_next_attr(it: &TokenIter) -> Option<Attribute> {
+    parse_attr_at(it.peek()?, it.peek_nth(1))
+}
+
+fn parse_attr_at(pound: &TokenTree, group: Option<&TokenTree>) -> Option<Attribute> {
+    let TokenTree::Punct(p) = pound else {
+        return None;
+    };
+    if p.as_char() != '#' {
+        return None;
+    }
+
+    // # ...
+    let Some(TokenTree::Group(g)) = group else {
+        return None;
+    };
+    if g.delimiter() != Delimiter::Bracket {
+        return None;
+    }
+
+    // #[ ... ]
+    let mut attr_ts = TokenStream::new();
+    attr_ts.extend(iter::once(pound.clone()));
+    attr_ts.extend(iter::once(TokenTree::Group(g.clone())));
+
+    parse_any_attr(attr_ts).ok()
+}
+
+/// Parses `#[cfg(...)]` or `#[cfg_keep(...)]`.
+///
+/// The returned flag is set for `cfg_keep`, which keeps the delimiters of its target group.
+pub fn parse_cfg(attr: &Attribute) -> syn::Result<Option<(Cfg, bool)>> {
+    if let Some(cfg) = Cfg::from_attr(attr)? {
+        return Ok(Some((cfg, false)));
+    }
+    Ok(Cfg::from_attr_named(attr, "cfg_keep")?.map(|cfg| (cfg, true)))
+}
+
+/// Consumes the `#[cfg(...)]` attributes stacked after the already consumed `last` one.
+///
+/// Returns the conjunction of all stacked predicates together with the tokens they apply to.
+/// The target is the next token tree, with the delimiters of a group stripped unless any of
+/// the stacked attributes is a `cfg_keep`, a whole `#[...]` attribute, or one of the tokens
+/// split up by the lexer, see [`take_target`].
+pub fn take_cfg_stack(
+    mut stack: Vec<Cfg>,
+    mut keep: bool,
+    last: &Attribute,
+    it: &mut TokenIter,
+) -> syn::Result<(Cfg, TokenStream)> {
+    let mut last = last.clone();
+    while let Some(tt) = it.next() {
+        let Some(attr) = peek_attr(&tt, it) else {
+            let target = match tt {
+                TokenTree::Group(g) if !keep => g.stream(),
+                tt => take_target(tt, it),
+            };
+            return Ok((Cfg::all_of(stack), target));
+        };
+
+        // consume #[...]
+        let group = it.next().unwrap();
+        match parse_cfg(&attr)? {
+            Some((cfg, keep_group)) => {
+                stack.push(cfg);
+                keep |= keep_group;
+                last = attr;
+            }
+            None => {
+                return Ok((Cfg::all_of(stack), TokenStream::from_iter([tt, group])));
+            }
+        }
+    }
+
+    // e.g. a #[cfg(...)] right before the closing delimiter of a group
+    Err(syn::Error::new_spanned(
+        last,
+        "expected a token tree after this attribute for it to apply to",
+    ))
+}
+
+/// Consumes the rest of a target starting with the already consumed `first` token.
+///
+/// Multi-character operators like `::` or `+=`, lifetimes like `'a`, negative literals like
+/// `-1` and blocks like `unsafe { ... }` consist of several token trees, but are a single target.
+fn take_target(first: TokenTree, it: &mut TokenIter) -> TokenStream {
+    let mut target = TokenStream::from(first.clone());
+    if let TokenTree::Ident(ident) = &first
+        && ["unsafe", "async", "const"].iter().any(|kw| ident == kw)
+    {
+        // unsafe { ... }, async move { ... } or const { ... }
+        let n = match it.peek() {
+            Some(TokenTree::Ident(ident)) if ident == "move" => 1,
+            _ => 0,
+        };
+        if matches!(it.peek_nth(n), Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace)
+        {
+            target.extend((0..=n).filter_map(|_| it.next()));
+        }
+        return target;
+    }
+    let TokenTree::Punct(p) = first else {
+        return target;
+    };
+
+    match (p.as_char(), it.peek()) {
+        ('\'', Some(TokenTree::Ident(_))) | ('-', Some(TokenTree::Literal(_))) => {
+            target.extend(it.next());
+            return target;
+        }
+        _ => {}
+    }
+
+    // the longest operator formed by the joint characters
+    let mut op = p.as_char().to_string();
+    let mut spacing = p.spacing();
+    let mut n = 0;
+    for tt in &it.0 {
+        let TokenTree::Punct(p) = tt else {
+            break;
+        };
+        if spacing != Spacing::Joint || op.len() == 3 {
+            break;
+        }
+        op.push(p.as_char());
+        spacing = p.spacing();
+        if OPERATORS.contains(&op.as_str()) {
+            n = op.len() - 1;
+        }
+    }
+    target.extend((0..n).filter_map(|_| it.next()));
+    target
+}
+
+/// Tracks whether a token starts an element of its group, i.e. an item, statement, field,
+/// variant, match arm or parameter.
+pub struct ElementStart {
+    start: bool,
+    pound: bool,
+    // the tokens since the last `;` or `{ ... }` group
+    element: Vec<TokenTree>,
+    body: bool,
+    macro_args: bool,
+    where_clause: bool,
+}
+
+impl ElementStart {
+    pub fn new() -> Self {
+        Self {
+            start: true,
+            pound: false,
+            element: Vec::new(),
+            body: false,
+            macro_args: false,
+            where_clause: false,
+        }
+    }
+
+    /// Advances past `tt` and returns whether it starts an element.
+    ///
+    /// Elements start after a `;`, a `,` outside of a where clause or a `{ ... }` group,
+    /// attributes in between are skipped.
+    pub fn next(&mut self, tt: &TokenTree) -> bool {
+        let at_start = self.start;
+        let is_attr =
+            self.pound && matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Bracket);
+        self.pound = matches!(tt, TokenTree::Punct(p) if p.as_char() == '#');
+        let ends = match tt {
+            TokenTree::Punct(p) => p.as_char() == ';',
+            TokenTree::Group(g) => g.delimiter() == Delimiter::Brace,
+            _ => false,
+        };
+        let comma = matches!(tt, TokenTree::Punct(p) if p.as_char() == ',');
+        if matches!(tt, TokenTree::Ident(ident) if ident == "where") {
+            self.where_clause = true;
+        }
+
+        self.body = matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace)
+            && is_container_header(&self.element);
+        self.macro_args = matches!(tt, TokenTree::Group(_))
+            && matches!(self.element.last(), Some(TokenTree::Punct(p)) if p.as_char() == '!');
+        if self.pound || is_attr {
+            self.start = at_start;
+            self.element.push(tt.clone());
+        } else if ends {
+            self.start = true;
+            self.where_clause = false;
+            self.element.clear();
+        } else {
+            self.start = comma && !self.where_clause;
+            self.element.push(tt.clone());
+        }
+        at_start
+    }
+
+    /// Returns the delimiter that decides which elements the last token, a group, consists of.
+    ///
+    /// The arguments of a macro call are not parsed by rustc, they are treated like the input of
+    /// `cfg_tt!` itself, which has no delimiter.
+    pub fn group_delimiter(&self, g: &Group) -> Option<Delimiter> {
+        (!self.macro_args).then(|| g.delimiter())
+    }
+
+    /// Returns whether the last token is the `{ ... }` body of a function or an `impl`, `trait`,
+    /// `mod` or `extern` block, in which rustc supports inner attributes itself.
+    pub fn is_container_body(&self) -> bool {
+        self.body
+    }
+
+    /// Marks the following token as not starting an element, e.g. after a consumed cfg target.
+    pub fn clear(&mut self) {
+        self.start = false;
+        self.pound = false;
+    }
+}
+
+/// Returns whether the `#[cfg(...)]` attribute `attr`, which starts an element of a group
+/// delimited by `delimiter` and whose brackets are still at the front of `it`, is supported by
+/// rustc in its position.
+///
+/// Such attributes apply to a whole item, statement, field, variant, match arm or parameter and
+/// are left for rustc instead of applying to the next token tree. They are recognized by the
+/// identifier the element starts with, so targets like groups or literals keep their meaning.
+/// Outside of `{ ... }` and `( ... )` groups, e.g. in the arguments of a macro call, only items
+/// and statements are recognized.
+pub fn is_native_cfg(attr: &Attribute, delimiter: Option<Delimiter>, it: &TokenIter) -> bool {
+    if !attr.path().is_ident("cfg") || !matches!(Cfg::from_attr(attr), Ok(Some(_))) {
+        return false;
+    }
+
+    // skip the brackets and any stacked #[cfg(...)]
+    let mut n = 1;
+    while let Some(attr) = it
+        .peek_nth(n)
+        .and_then(|tt| parse_attr_at(tt, it.peek_nth(n + 1)))
+    {
+        if !attr.path().is_ident("cfg") {
+            return false;
+        }
+        n += 2;
+    }
+    if !matches!(it.peek_nth(n), Some(TokenTree::Ident(_))) {
+        return false;
+    }
+    let is_item = is_item_start(it, n);
+    let next = it.peek_nth(n + 1);
+    let is_punct = |tt: Option<&TokenTree>, c: char| matches!(tt, Some(TokenTree::Punct(p)) if p.as_char() == c && p.spacing() == Spacing::Alone);
+    // `name: ...`, but not `name::...`
+    let is_named = is_punct(next, ':');
+
+    match delimiter {
+        // items and statements
+        None => is_item,
+        // fn parameters
+        Some(Delimiter::Parenthesis) => is_named,
+        // items, statements, fields, variants and match arms
+        Some(Delimiter::Brace) => {
+            // `A,`, `A(..),`, `A { .. },` or `A = 1,`
+            let is_variant = is_punct(next, ',')
+                || matches!(next, Some(TokenTree::Group(g)) if g.delimiter() != Delimiter::Bracket)
+                    && is_punct(it.peek_nth(n + 2), ',')
+                || is_punct(next, '=') && is_discriminant(it, n + 2);
+            is_item || is_named || is_variant || is_match_arm(it, n)
+        }
+        _ => false,
+    }
+}
+
+/// Returns whether the element starting at the `n`th token of `it` is an item or statement that
+/// starts with its keyword, like `fn f() {}` or `let x = 1;`.
+///
+/// `const { ... }` and `const fn`, `static || ...` closures and contextual keywords used as
+/// identifiers, like a variable named `union`, are not.
+fn is_item_start(it: &TokenIter, n: usize) -> bool {
+    let Some(TokenTree::Ident(ident)) = it.peek_nth(n) else {
+        return false;
+    };
+    let next_is_ident = matches!(it.peek_nth(n + 1), Some(TokenTree::Ident(_)));
+    match ident.to_string().as_str() {
+        // const X: T = ...;
+        "const" => {
+            next_is_ident
+                && matches!(it.peek_nth(n + 2), Some(TokenTree::Punct(p)) if p.as_char() == ':')
+        }
+        // static X: T = ...; or static mut X: T = ...;
+        "static" => next_is_ident,
+        // union U { ... } or union U<T> { ... }
+        "union" => {
+            next_is_ident
+                && match it.peek_nth(n + 2) {
+                    Some(TokenTree::Group(g)) => g.delimiter() == Delimiter::Brace,
+                    Some(TokenTree::Punct(p)) => p.as_char() == '<',
+                    _ => false,
+                }
+        }
+        keyword => ITEM_KEYWORDS.contains(&keyword),
+    }
+}
+
+/// Returns whether the element continuing at the `n`th token of `it`, after a `=`, is the
+/// discriminant of a variant, i.e. ends with a `,` rather than a `;`.
+fn is_discriminant(it: &TokenIter, mut n: usize) -> bool {
+    while let Some(tt) = it.peek_nth(n) {
+        match tt {
+            TokenTree::Punct(p) if p.as_char() == ',' => return true,
+            TokenTree::Punct(p) if p.as_char() == ';' => return false,
+            _ => n += 1,
+        }
+    }
+    false
+}
+
+/// Returns whether the element starting at the `n`th token of `it` contains a top-level `=>`.
+fn is_match_arm(it: &TokenIter, mut n: usize) -> bool {
+    while let Some(tt) = it.peek_nth(n) {
+        let TokenTree::Punct(p) = tt else {
+            n += 1;
+            continue;
+        };
+        match p.as_char() {
+            ',' | ';' => return false,
+            '=' if p.spacing() == Spacing::Joint => {
+                if matches!(it.peek_nth(n + 1), Some(TokenTree::Punct(p)) if p.as_char() == '>') {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+        n += 1;
+    }
+    false
+}
+
+/// Returns whether `attr` contains a `$` metavariable, like `#[cfg($pred)]` in the body of a
+/// `macro_rules!`. Its arguments are only known once that macro is expanded, so it is passed on.
+pub fn has_metavariable(attr: &Attribute) -> bool {
+    fn core(ts: TokenStream) -> bool {
+        ts.into_iter().any(|tt| match tt {
+            TokenTree::Punct(p) => p.as_char() == '$',
+            TokenTree::Group(g) => core(g.stream()),
+            _ => false,
+        })
+    }
+    core(attr.meta.to_token_stream())
+}
+
+/// Returns whether `attr` is handled by `cfg_tt` itself rather than passed on.
+///
+/// `#[cfg_attr(...)]` is not included, it is replaced by the attributes it expands to, which
+/// belong to the same element.
+pub fn is_directive(attr: &Attribute) -> bool {
+    [
+        "cfg",
+        "cfg_keep",
+        "cfg_elem",
+        "cfg_begin",
+        "cfg_end",
+        "cfg_match",
+        "cfg_else",
+        "cfg_else_if",
+    ]
+    .iter()
+    .any(|name| attr.path().is_ident(name))
+        || is_verbatim(attr)
+}
+
+/// Returns whether `attr` is `#[cfg_tt::verbatim]`.
+pub fn is_verbatim(attr: &Attribute) -> bool {
+    let Meta::Path(path) = &attr.meta else {
+        return false;
+    };
+    let segments = path
+        .segments
+        .iter()
+        .map(|s| s.ident.to_string())
+        .collect::<Vec<_>>();
+    path.leading_colon.is_none() && segments == ["cfg_tt", "verbatim"]
+}
+
+/// Consumes the group after an already consumed `#[cfg_tt::verbatim]`, which is passed on
+/// unchanged.
+pub fn take_verbatim(marker: &Attribute, it: &mut TokenIter) -> syn::Result<Group> {
+    match it.next() {
+        Some(TokenTree::Group(g)) => Ok(g),
+        _ => Err(syn::Error::new_spanned(
+            marker,
+            "expected a `{ ... }` group after `#[cfg_tt::verbatim]`",
+        )),
+    }
+}
+
+/// Returns whether `attr` is a marker without arguments, like `#[cfg_else]`.
+pub fn is_marker(attr: &Attribute, name: &str) -> bool {
+    matches!(&attr.meta, Meta::Path(path) if path.is_ident(name))
+}
+
+/// Consumes the target of an already consumed `#[cfg(...)]` and any `#[cfg_else_if(...)]` and
+/// `#[cfg_else]` branches chained after it.
+///
+/// Returns every branch with its effective predicate, i.e. a branch only applies if none of the
+/// earlier ones do.
+pub fn take_cfg_chain(
+    cfg: Cfg,
+    keep: bool,
+    attr: &Attribute,
+    it: &mut TokenIter,
+) -> syn::Result<Vec<(Cfg, TokenStream)>> {
+    let mut branches = vec![take_cfg_stack(vec![cfg], keep, attr, it)?];
+    while let Some(attr) = peek_next_attr(it) {
+        let stack = if let Some(cfg) = Cfg::from_attr_named(&attr, "cfg_else_if")? {
+            vec![cfg]
+        } else if is_marker(&attr, "cfg_else") {
+            vec![]
+        } else {
+            break;
+        };
+
+        // consume #[cfg_else_if(...)] or #[cfg_else]
+        let _ = it.next();
+        let _ = it.next();
+        let is_else = stack.is_empty();
+        branches.push(take_cfg_stack(stack, false, &attr, it)?);
+        if is_else {
+            break;
+        }
+    }
+
+    let (cfgs, targets): (Vec<_>, Vec<_>) = branches.into_iter().unzip();
+    Ok(Cfg::first_match(&cfgs).into_iter().zip(targets).collect())
+}
+
+/// Consumes the tokens up to the `#[cfg_end]` matching an already consumed `begin` marker.
+///
+/// Nested `#[cfg_begin(...)]` ... `#[cfg_end]` ranges are kept in the returned tokens.
+pub fn take_cfg_range(begin: &Attribute, it: &mut TokenIter) -> syn::Result<TokenStream> {
+    let mut depth = 0usize;
+    let mut range = TokenStream::new();
+    while let Some(tt) = it.next() {
+        if let Some(attr) = peek_attr(&tt, it) {
+            if attr.path().is_ident("cfg_begin") {
+                depth += 1;
+            } else if is_marker(&attr, "cfg_end") {
+                // consume #[cfg_end]
+                let group = it.next().unwrap();
+                if depth == 0 {
+                    return Ok(range);
+                }
+                depth -= 1;
+                range.extend([tt, group]);
+                continue;
+            }
+        }
+        range.extend([tt]);
+    }
+
+    Err(syn::Error::new_spanned(
+        begin,
+        "unbalanced `#[cfg_begin(...)]`, expected a matching `#[cfg_end]` in the same group",
+    ))
+}
+
+/// Consumes the list element after an already consumed `#[cfg_elem(...)]`, together with the
+/// comma separating it from the next one.
+///
+/// The element ends at the next `,` outside of `<...>`, or before a `;`, an unmatched `>` or the
+/// end of the group.
+pub fn take_cfg_elem(attr: &Attribute, it: &mut TokenIter) -> syn::Result<TokenStream> {
+    let mut elem = TokenStream::new();
+    let mut depth = 0usize;
+    let mut empty = true;
+    while let Some(tt) = it.peek() {
+        if let TokenTree::Punct(p) = tt {
+            let joint = p.spacing() == Spacing::Joint;
+            let next = match it.peek_nth(1) {
+                Some(TokenTree::Punct(next)) => Some(next.as_char()),
+                _ => None,
+            };
+            match p.as_char() {
+                ',' if depth == 0 => {
+                    if empty {
+                        break;
+                    }
+                    elem.extend(it.next());
+                    return Ok(elem);
+                }
+                ';' if depth == 0 => break,
+                // `->` and `=>` are consumed with their first character, `<=` and `>=` are
+                // comparisons
+                '-' | '=' if joint && next == Some('>') => elem.extend(it.next()),
+                '<' | '>' if joint && next == Some('=') => {}
+                '<' => depth += 1,
+                '>' if depth == 0 => break,
+                '>' => depth -= 1,
+                _ => {}
+            }
+        }
+        elem.extend(it.next());
+        empty = false;
+    }
+
+    if empty {
+        return Err(syn::Error::new_spanned(
+            attr,
+            "expected a list element after this attribute for it to apply to",
+        ));
+    }
+    Ok(elem)
+}
+
+/// Consumes the block after an already consumed `#[cfg_match]` and parses its arms.
+pub fn take_cfg_match(
+    marker: &Attribute,
+    it: &mut TokenIter,
+) -> syn::Result<Vec<(Cfg, TokenStream)>> {
+    match it.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+            Ok(syn::parse2::<CfgMatch>(g.stream())?.0)
+        }
+        _ => Err(syn::Error::new_spanned(
+            marker,
+            "expected a `{ pred => ..., _ => ... }` block after `#[cfg_match]`",
+        )),
+    }
+}
+
+pub fn find_cfg_attrs(ts: TokenStream) -> syn::Result<Vec<Cfg>> {
+    fn core(ts: TokenStream, delimiter: Option<Delimiter>, out: &mut Vec<Cfg>) -> syn::Result<()> {
+        let mut it = TokenIter::new(ts);
+        let mut start = ElementStart::new();
 
         while let Some(tt) = it.next() {
+            let at_start = start.next(&tt);
             match &tt {
                 TokenTree::Group(g) => {
-                    core(g.stream(), out);
+                    let mut inner = TokenIter::new(g.stream());
+                    if !start.is_container_body()
+                        && let Some(cfg) = take_inner_cfg(&mut inner)?
+                    {
+                        // { #![cfg(...)] ... }
+                        out.push(cfg);
+                    }
+                    core(inner.collect(), start.group_delimiter(g), out)?;
                 }
                 TokenTree::Punct(p) if p.as_char() == '#' => {
-                    // # ...
-                    let Some(TokenTree::Group(g)) = it.peek() else {
+                    let Some(attr) = peek_attr(&tt, &it) else {
                         continue;
                     };
-                    if g.delimiter() != Delimiter::Bracket {
+                    if has_metavariable(&attr) {
+                        // left for the macro_rules! it is part of
                         continue;
                     }
-
-                    // #[ ... ]
-                    let mut attr_ts = TokenStream::new();
-                    attr_ts.extend(iter::once(tt.clone()));
-                    attr_ts.extend(iter::once(TokenTree::Group(g.clone())));
-
-                    let Ok(attr) = parse_any_attr(attr_ts) else {
+                    if at_start && is_native_cfg(&attr, delimiter, &it) {
+                        // left for rustc, like any other attribute
                         continue;
-                    };
-                    if let Some(cfg) = Cfg::from_attr(&attr) {
+                    }
+                    if is_directive(&attr) {
+                        start.clear();
+                    }
+                    if let Some((cfg, keep)) = parse_cfg(&attr)? {
                         // #[cfg(...)]
+                        let _ = it.next();
+
+                        // stacked #[cfg(...)] attributes apply together, chained
+                        // #[cfg_else_if(...)] and #[cfg_else] branches exclude each other
+                        for (cfg, target) in take_cfg_chain(cfg, keep, &attr, &mut it)? {
+                            out.push(cfg);
+                            core(target, delimiter, out)?;
+                        }
+                    } else if let Some((cfg, attrs)) = Cfg::from_cfg_attr(&attr)? {
+                        // #[cfg_attr(...)], its attributes apply to the following tokens
+                        if let Some(bracket) = it.next() {
+                            start.next(&bracket);
+                        }
+                        out.push(cfg);
+                        it.push_front(attrs);
+                    } else if let Some(cfg) = Cfg::from_attr_named(&attr, "cfg_elem")? {
+                        // #[cfg_elem(...)] element,
+                        let _ = it.next();
+                        let elem = take_cfg_elem(&attr, &mut it)?;
                         out.push(cfg);
+                        core(elem, delimiter, out)?;
+                    } else if let Some(cfg) = Cfg::from_attr_named(&attr, "cfg_begin")? {
+                        // #[cfg_begin(...)] ... #[cfg_end]
+                        let _ = it.next();
+                        let range = take_cfg_range(&attr, &mut it)?;
+                        out.push(cfg);
+                        core(range, delimiter, out)?;
+                    } else if is_marker(&attr, "cfg_match") {
+                        // #[cfg_match] { ... }
+                        let _ = it.next();
+                        for (cfg, body) in take_cfg_match(&attr, &mut it)? {
+                            out.push(cfg);
+                            core(body, delimiter, out)?;
+                        }
+                    } else if is_verbatim(&attr) {
+                        // #[cfg_tt::verbatim] { ... } is not looked into
                         let _ = it.next();
+                        take_verbatim(&attr, &mut it)?;
+                    } else if is_marker(&attr, "cfg_end") {
+                        return Err(syn::Error::new_spanned(
+                            attr,
+                            "unbalanced `#[cfg_end]`, expected a preceding `#[cfg_begin(...)]` in the same group",
+                        ));
+                    } else if is_marker(&attr, "cfg_else") || attr.path().is_ident("cfg_else_if") {
+                        return Err(syn::Error::new_spanned(
+                            attr,
+                            "`#[cfg_else]` and `#[cfg_else_if(...)]` must directly follow the target of a `#[cfg(...)]` or `#[cfg_else_if(...)]`",
+                        ));
                     }
                 }
                 _ => {}
             }
         }
+        Ok(())
+    }
+
+    let mut out = Vec::new();
+    core(ts, None, &mut out)?;
+    Ok(out)
+}
+
+/// Returns the cfg attributes in `ts` whose predicates mention any of `atoms`.
+///
+/// A `#[cfg_match]` is returned if any of its arms does.
+pub fn find_cfg_sites(ts: TokenStream, atoms: &[Meta]) -> Vec<Attribute> {
+    fn core(ts: TokenStream, atoms: &[Meta], out: &mut Vec<Attribute>) {
+        let mut it = TokenIter::new(ts);
+        while let Some(tt) = it.next() {
+            if let TokenTree::Group(g) = &tt {
+                core(g.stream(), atoms, out);
+                continue;
+            }
+            let Some(attr) = peek_attr(&tt, &it) else {
+                continue;
+            };
+
+            if is_verbatim(&attr) {
+                let _ = it.next();
+                let _ = it.next();
+                continue;
+            }
+            let cfgs: Vec<Cfg> = if is_marker(&attr, "cfg_match") {
+                let _ = it.next();
+                take_cfg_match(&attr, &mut it)
+                    .map(|arms| arms.into_iter().map(|(cfg, _)| cfg).collect())
+                    .unwrap_or_default()
+            } else {
+                attr_cfg(&attr).into_iter().collect()
+            };
+            if cfgs
+                .iter()
+                .flat_map(Cfg::atoms)
+                .any(|atom| atoms.contains(&atom))
+            {
+                out.push(attr);
+            }
+        }
     }
 
     let mut out = Vec::new();
-    core(ts, &mut out);
+    core(ts, atoms, &mut out);
     out
 }
+
+/// Returns the predicate of any cfg attribute other than `#[cfg_match]`.
+fn attr_cfg(attr: &Attribute) -> Option<Cfg> {
+    if let Ok(Some((cfg, _))) = parse_cfg(attr) {
+        return Some(cfg);
+    }
+    for name in ["cfg_else_if", "cfg_elem", "cfg_begin"] {
+        if let Ok(Some(cfg)) = Cfg::from_attr_named(attr, name) {
+            return Some(cfg);
+        }
+    }
+    let (cfg, _) = Cfg::from_cfg_attr(attr).ok()??;
+    Some(cfg)
+}
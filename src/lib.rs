@@ -1,17 +1,26 @@
 #![doc = include_str!("../README.md")]
 
+mod bdd;
+use bdd::*;
 mod cfg;
 use cfg::*;
 mod find;
 use find::*;
+mod options;
+use options::*;
+mod rules;
+mod single;
+use single::*;
+mod split;
+use split::*;
 
-use std::{collections::HashSet, iter};
+use std::collections::{HashMap, HashSet, hash_map::Entry};
 
 use proc_macro2::{Delimiter, Group, TokenStream, TokenTree};
-use quote::ToTokens;
+use quote::{ToTokens, quote};
 use syn::{
-    Item, Stmt,
-    parse::{Parse, ParseStream},
+    Block, Expr, ImplItem, Item, Stmt, TraitItem,
+    parse::{Parse, ParseStream, Parser},
 };
 
 struct Many<T>(Vec<T>);
@@ -26,61 +35,135 @@ impl<T: Parse> Parse for Many<T> {
     }
 }
 
-fn expand_for_cfg(ts: TokenStream, active_cfg: &Cfg) -> TokenStream {
-    let mut it = ts.into_iter().peekable();
+fn expand_for_cfg(ts: TokenStream, active_cfg: &Cfg, solver: &mut Solver) -> TokenStream {
+    expand_in(ts, None, active_cfg, solver)
+}
+
+/// Expands `ts`, which is the content of a group delimited by `delimiter`, for `active_cfg`.
+fn expand_in(
+    ts: TokenStream,
+    delimiter: Option<Delimiter>,
+    active_cfg: &Cfg,
+    solver: &mut Solver,
+) -> TokenStream {
+    let mut it = TokenIter::new(ts);
     let mut out = TokenStream::new();
+    let mut start = ElementStart::new();
     while let Some(tt) = it.next() {
+        let at_start = start.next(&tt);
         match &tt {
             TokenTree::Group(g) => {
-                let expanded = expand_for_cfg(g.stream(), active_cfg);
-                let expanded = TokenTree::Group(Group::new(g.delimiter(), expanded));
-                out.extend([expanded]);
+                let mut inner = TokenIter::new(g.stream());
+                // malformed predicates were already reported by find_cfg_attrs
+                if !start.is_container_body()
+                    && let Ok(Some(cfg)) = take_inner_cfg(&mut inner)
+                    && !solver.implies(active_cfg, &cfg)
+                {
+                    // { #![cfg(...)] ... } is removed as a whole
+                    continue;
+                }
+                let delimiter = start.group_delimiter(g);
+                let expanded = expand_in(inner.collect(), delimiter, active_cfg, solver);
+                // keep the span, so that errors point at the original delimiters
+                let mut group = Group::new(g.delimiter(), expanded);
+                group.set_span(g.span());
+                out.extend([TokenTree::Group(group)]);
             }
             TokenTree::Punct(p) if p.as_char() == '#' => {
-                // # ...
-                let Some(TokenTree::Group(g)) = it.peek() else {
+                let Some(attr) = peek_attr(&tt, &it) else {
                     out.extend([tt]);
                     continue;
                 };
-                if g.delimiter() != Delimiter::Bracket {
+                if has_metavariable(&attr) {
+                    // left for the macro_rules! it is part of
                     out.extend([tt]);
                     continue;
                 }
-
-                // #[ ... ]
-                let mut attr_ts = TokenStream::new();
-                attr_ts.extend(iter::once(tt.clone()));
-                attr_ts.extend(iter::once(TokenTree::Group(g.clone())));
-
-                let Ok(attr) = parse_any_attr(attr_ts) else {
-                    out.extend([tt]);
-                    continue;
-                };
-                let Some(cfg) = Cfg::from_attr(&attr) else {
-                    out.extend([tt]);
-                    continue;
-                };
-                if !attr.path().is_ident("cfg") {
+                if at_start && is_native_cfg(&attr, delimiter, &it) {
+                    // left for rustc
                     out.extend([tt]);
                     continue;
                 }
+                if is_directive(&attr) {
+                    start.clear();
+                }
+
+                // malformed predicates were already reported by find_cfg_attrs
+                if let Ok(Some((cfg, keep))) = parse_cfg(&attr) {
+                    // consume #[cfg(...)]
+                    let _ = it.next();
+
+                    // stacked #[cfg(...)] attributes, the target they apply to and
+                    // any chained #[cfg_else_if(...)] and #[cfg_else] branches
+                    let branches = take_cfg_chain(cfg, keep, &attr, &mut it)
+                        .unwrap_or_else(|e| vec![(Cfg::All(Vec::new()), e.to_compile_error())]);
+                    for (cfg, target) in branches {
+                        if solver.implies(active_cfg, &cfg) {
+                            // active
+                            let expanded = expand_in(target, delimiter, active_cfg, solver);
+                            out.extend([expanded]);
+                        } else {
+                            // dont emit anything
+                        }
+                    }
+                } else if let Ok(Some((cfg, attrs))) = Cfg::from_cfg_attr(&attr) {
+                    // consume #[cfg_attr(...)]
+                    if let Some(bracket) = it.next() {
+                        start.next(&bracket);
+                    }
 
-                // consume #[cfg(...)]
-                let _ = it.next();
-
-                // target of cfg
-                let Some(target) = it.next() else { continue };
-                if active_cfg.implies(&cfg) {
-                    // active
-                    let target = if let TokenTree::Group(g) = target {
-                        g.stream()
-                    } else {
-                        target.into_token_stream()
-                    };
-                    let expanded = expand_for_cfg(target, active_cfg);
-                    out.extend([expanded]);
+                    if solver.implies(active_cfg, &cfg) {
+                        // active, the attributes are expanded together with the following
+                        // tokens, so that a resulting #[cfg(...)] applies to them
+                        it.push_front(attrs);
+                    }
+                } else if let Ok(Some(cfg)) = Cfg::from_attr_named(&attr, "cfg_elem") {
+                    // consume #[cfg_elem(...)]
+                    let _ = it.next();
+
+                    // the list element up to and including its comma
+                    let elem =
+                        take_cfg_elem(&attr, &mut it).unwrap_or_else(|e| e.to_compile_error());
+                    if solver.implies(active_cfg, &cfg) {
+                        let expanded = expand_in(elem, delimiter, active_cfg, solver);
+                        out.extend([expanded]);
+                    }
+                } else if let Ok(Some(cfg)) = Cfg::from_attr_named(&attr, "cfg_begin") {
+                    // consume #[cfg_begin(...)]
+                    let _ = it.next();
+
+                    // tokens up to the matching #[cfg_end]
+                    let range =
+                        take_cfg_range(&attr, &mut it).unwrap_or_else(|e| e.to_compile_error());
+                    if solver.implies(active_cfg, &cfg) {
+                        let expanded = expand_in(range, delimiter, active_cfg, solver);
+                        out.extend([expanded]);
+                    }
+                } else if is_marker(&attr, "cfg_match") {
+                    // consume #[cfg_match]
+                    let _ = it.next();
+
+                    // the first arm whose predicate holds supplies the tokens
+                    let arms = take_cfg_match(&attr, &mut it)
+                        .unwrap_or_else(|e| vec![(Cfg::All(Vec::new()), e.to_compile_error())]);
+                    if let Some((_, body)) = arms
+                        .into_iter()
+                        .find(|(cfg, _)| solver.implies(active_cfg, cfg))
+                    {
+                        let expanded = expand_in(body, delimiter, active_cfg, solver);
+                        out.extend([expanded]);
+                    }
+                } else if is_verbatim(&attr) {
+                    // consume #[cfg_tt::verbatim]
+                    let _ = it.next();
+
+                    // the group is emitted as is, including its delimiters
+                    match take_verbatim(&attr, &mut it) {
+                        Ok(group) => out.extend([TokenTree::Group(group)]),
+                        Err(err) => out.extend([err.to_compile_error()]),
+                    }
                 } else {
-                    // dont emit anything
+                    out.extend([tt]);
                 }
             }
             _ => {
@@ -92,69 +175,79 @@ fn expand_for_cfg(ts: TokenStream, active_cfg: &Cfg) -> TokenStream {
     out
 }
 
-fn generate_all_combinations(cfgs: Vec<Cfg>) -> Vec<Cfg> {
-    fn core<T: Clone>(
-        items: &[T],
+/// Constructs every possible combination of `cfgs` being active or not.
+///
+/// Returns `None` if there are more than `limit` of them.
+fn generate_all_combinations(
+    cfgs: Vec<Cfg>,
+    limit: usize,
+    solver: &mut Solver,
+) -> Option<Vec<Cfg>> {
+    fn core(
+        items: &[Cfg],
         i: usize,
-        acc: &mut Vec<(T, bool)>,
-        out: &mut impl FnMut(&Vec<(T, bool)>),
-    ) {
-        if i == items.len() {
-            out(acc);
-            return;
+        acc: &mut Vec<Cfg>,
+        reachable: Bdd,
+        solver: &mut Solver,
+        out: &mut Vec<Cfg>,
+        limit: usize,
+    ) -> bool {
+        // Skip contradictions like all(a, all(not(a), b)) together with all their
+        // extensions, they are never active
+        if !solver.is_possible(reachable) {
+            return true;
         }
 
-        // excluded
-        acc.push((items[i].clone(), false));
-        core(items, i + 1, acc, out);
-        acc.pop();
+        if i == items.len() {
+            out.push(Cfg::all_of(acc.clone()));
+            return out.len() <= limit;
+        }
 
-        // included
-        acc.push((items[i].clone(), true));
-        core(items, i + 1, acc, out);
-        acc.pop();
+        // excluded, then included
+        for cfg in [Cfg::Not(Box::new(items[i].clone())), items[i].clone()] {
+            let bdd = solver.bdd(&cfg);
+            let next = solver.and(reachable, bdd);
+            acc.push(cfg);
+            let within_limit = core(items, i + 1, acc, next, solver, out, limit);
+            acc.pop();
+            if !within_limit {
+                return false;
+            }
+        }
+        true
     }
 
+    // only constant cfgs, which are the same in the single configuration
     if cfgs.is_empty() {
-        return Vec::new();
+        return Some(vec![Cfg::All(Vec::new())]);
     }
 
     let mut acc = Vec::with_capacity(cfgs.len());
-    let mut out = Vec::with_capacity(cfgs.len() * cfgs.len());
-    core(&cfgs, 0, &mut acc, &mut |cfgs| {
-        let mut list = cfgs
-            .iter()
-            .cloned()
-            .map(
-                |(cfg, active)| {
-                    if active { cfg } else { Cfg::Not(Box::new(cfg)) }
-                },
-            )
-            .collect::<Vec<_>>();
-        out.push(if list.len() == 1 {
-            list.pop().unwrap()
-        } else {
-            Cfg::All(list)
-        });
-    });
-    out
+    let mut out = Vec::new();
+    let all = solver.bdd(&Cfg::All(Vec::new()));
+    core(&cfgs, 0, &mut acc, all, solver, &mut out, limit).then_some(out)
 }
 
 fn find_base_cfgs(input: impl IntoIterator<Item = Cfg>) -> Vec<Cfg> {
     let mut cfgs = HashSet::new();
+    // in order of first occurrence, so that the output is deterministic
+    let mut ordered = Vec::new();
 
     // Remove duplicates and negations
     for cfg in input.into_iter() {
-        match cfg {
-            Cfg::Not(inner) => cfgs.insert(*inner),
-            Cfg::All(list) | Cfg::Any(list) if list.is_empty() => false,
-            Cfg::All(list) | Cfg::Any(list) if list.len() == 1 => cfgs.insert(list[0].clone()),
-            _ => cfgs.insert(cfg),
+        let cfg = match cfg {
+            Cfg::Not(inner) => *inner,
+            Cfg::All(list) | Cfg::Any(list) if list.is_empty() => continue,
+            Cfg::All(mut list) | Cfg::Any(mut list) if list.len() == 1 => list.pop().unwrap(),
+            _ => cfg,
         };
+        if cfgs.insert(cfg.clone()) {
+            ordered.push(cfg);
+        }
     }
 
     // Remove all() if all inner cfgs exist
-    let cfgs: Vec<Cfg> = cfgs
+    let cfgs: Vec<Cfg> = ordered
         .iter()
         .filter(|cfg| match cfg {
             Cfg::All(xs) => !xs.iter().all(|child| match child {
@@ -177,44 +270,533 @@ fn find_base_cfgs(input: impl IntoIterator<Item = Cfg>) -> Vec<Cfg> {
 /// Each `#[cfg(...)]` attribute applies to **exactly the next `TokenTree`**:
 /// - an identifier (e.g. `foo`)
 /// - a literal (e.g. `42`)
-/// - a punctuation token (e.g. `+`)
+/// - a punctuation token or operator (e.g. `+`, `::` or `+=`)
+/// - a lifetime (e.g. `'a`) or a negative literal (e.g. `-1`)
+/// - a block like `unsafe { ... }`, `async { ... }` or `const { ... }`
 /// - a group (`{}`, `()`, `[]`)
+/// - an attribute (`#[...]`)
+///
+/// Stacked attributes like `#[cfg(a)] #[cfg(b)]` apply to the same token tree and
+/// are combined as `#[cfg(all(a, b))]`.
+///
+/// A `#[cfg(...)]` in front of an item or `let` statement that starts with its keyword, a
+/// named field or parameter, an enum variant or a match arm is supported by rustc already,
+/// it is left as is and applies to the whole element. In front of a visibility, a qualifier
+/// like `unsafe` or an expression statement, it applies to just the next token tree.
+///
+/// `#[cfg_attr(pred, attrs...)]` is resolved the same way, anywhere in the input.
 ///
 /// To conditionally include more than one token tree, wrap them in a group.
+/// The delimiters of a group are removed, use `#[cfg_keep(...)]` instead of
+/// `#[cfg(...)]` to keep them.
+///
+/// A `#[cfg(...)]` target may be followed by `#[cfg_else_if(...)]` and `#[cfg_else]`
+/// branches, of which only the first one whose predicate holds is included.
+///
+/// `#[cfg_elem(...)]` at the start of an element of a comma separated list, like generics,
+/// where clauses, arguments or tuple fields, applies to the whole element up to the next
+/// comma outside of `<...>` and removes that comma together with the element.
+///
+/// `#[cfg_match] { pred => { ... }, ..., _ => { ... } }` selects the tokens of the
+/// first arm whose predicate holds.
+///
+/// An inner `#![cfg(...)]` at the start of a group makes the whole group conditional,
+/// and at the start of the input all of it. In the bodies of functions and `impl`,
+/// `trait`, `mod` and `extern` blocks it is left to rustc.
+///
+/// Alternatively, all tokens between `#[cfg_begin(...)]` and the matching
+/// `#[cfg_end]` in the same group are conditionally included. Such ranges may be nested.
+///
+/// The group after `#[cfg_tt::verbatim]` is emitted unchanged, including its delimiters,
+/// e.g. to pass `#[cfg]`s on to another macro that interprets them itself.
 ///
 /// After cfg filtering, the remaining tokens are emitted unchanged and must
-/// form valid Rust code.
+/// form valid Rust code. This is checked for every configuration, not just the
+/// one being compiled, and errors name the configuration they occur in.
+///
+/// Every top-level item (or statement) of the input is expanded on its own and
+/// only duplicated for the cfgs it contains. Items without any cfg are emitted once,
+/// unchanged. The statements of a function and the members of `impl`, `trait`, `mod`
+/// and `extern` blocks are expanded the same way, unless the signature or header contains
+/// a cfg itself.
+///
+/// If the input is a single expression, e.g. in `let x = cfg_tt!(...)`, or the tail
+/// expression of a function, the configurations are emitted as the `#[cfg]`'d arms of
+/// a `match ()`.
+///
+/// Every item is expanded for at most 1024 configurations by default. A different limit
+/// can be set with `#![cfg_tt(limit = N)]` at the start of the input.
+///
+/// With `#![cfg_tt(single_expansion)]`, the configurations are not expanded up front. Instead,
+/// the compiler resolves every cfg once through a chain of `#[cfg]`'d macro calls and only the
+/// active configuration is expanded, so the output grows linearly with the number of cfgs. The
+/// other configurations are then not checked. The calls go through `::cfg_tt`, if the crate is
+/// renamed or re-exported, pass its path as in `#![cfg_tt(single_expansion, crate = ::my_cfg_tt)]`.
 #[proc_macro]
 pub fn cfg_tt(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let content: TokenStream = input.into();
+    let mut it = TokenIter::new(input.into());
+    let (options, cfg) = match take_leading_attrs(&mut it) {
+        Ok(taken) => taken,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let content = it.collect();
+
+    let expanded = if options.single_expansion {
+        expand_single(content, &options.crate_path).unwrap_or_else(|err| err.to_compile_error())
+    } else {
+        expand_items(content, &options)
+    };
+    match cfg {
+        Some(cfg) => split_parsed(expanded)
+            .into_iter()
+            .flat_map(|item| [cfg.to_token_stream(), item])
+            .collect::<TokenStream>()
+            .into(),
+        None => expanded.into(),
+    }
+}
+
+/// Consumes the `#![cfg_tt(...)]` and `#![cfg(...)]` attributes at the start of the input of
+/// [`cfg_tt!`], in any order.
+///
+/// Returns the options together with the conjunction of the cfgs, which apply to all of the input.
+fn take_leading_attrs(it: &mut TokenIter) -> syn::Result<(Options, Option<Cfg>)> {
+    let mut options = Options::default();
+    let mut cfgs = Vec::new();
+    loop {
+        options.take(it)?;
+        match take_inner_cfg(it)? {
+            Some(cfg) => cfgs.push(cfg),
+            None => break,
+        }
+    }
+    Ok((options, (!cfgs.is_empty()).then(|| Cfg::all_of(cfgs))))
+}
+
+/// Resolves the next cfg of a `single_expansion` chain, or expands its tokens for the values of
+/// all of them.
+#[doc(hidden)]
+#[proc_macro]
+pub fn __cfg_tt_eval(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    eval(input.into())
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Expands every item of `content` on its own, so that it only gets duplicated for the cfgs
+/// it contains.
+fn expand_items(content: TokenStream, options: &Options) -> TokenStream {
+    let mut out = TokenStream::new();
+    for item in split_items(content) {
+        match expand_item(item, options) {
+            Ok(expanded) => out.extend([expanded]),
+            Err(err) => out.extend([err.to_compile_error()]),
+        }
+    }
+    out
+}
 
-    // Collect all occurances or #[cfg()] in the input
-    let cfgs = find_cfg_attrs(content.clone());
+/// Attribute form of [`cfg_tt!`], for a single `fn`, `struct`, `enum`, `impl`, `trait` or
+/// inline `mod` item.
+///
+/// The item, including its other outer attributes, is expanded with the same semantics as
+/// the input of [`cfg_tt!`], without an extra level of indentation:
+/// ```rust
+/// #[cfg_tt::cfg_tt_attr]
+/// pub fn f() -> i32 {
+///     #[cfg(windows)] { 1 }
+///     #[cfg_else] { 2 }
+/// }
+/// ```
+///
+/// As rustc parses the item before passing it on, it has to be valid Rust syntax, so the
+/// attributes can only be used where attributes are allowed, like on statements or parameters.
+///
+/// Options like `limit = N`, `single_expansion` or `crate = path` are passed as arguments, e.g.
+/// `#[cfg_tt_attr(limit = 4096)]`.
+///
+/// A proc-macro crate can't export an attribute and a function-like macro of the same name,
+/// hence the different name.
+#[proc_macro_attribute]
+pub fn cfg_tt_attr(
+    args: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let mut options = Options::default();
+    options
+        .parse_args(args.into())
+        .and_then(|()| {
+            if options.single_expansion {
+                expand_single(item.into(), &options.crate_path)
+            } else {
+                expand_item(item.into(), &options)
+            }
+        })
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+fn expand_item(content: TokenStream, options: &Options) -> syn::Result<TokenStream> {
+    // Collect all occurances or #[cfg()] in the item
+    let cfgs = find_cfg_attrs(content.clone())?;
     if cfgs.is_empty() {
         // Nothing to do
-        return content.into();
+        return Ok(content);
+    }
+
+    // If only the statements of a function or the members of an impl, trait, mod or extern
+    // block contain cfgs, the item itself is emitted once and only the affected statements or
+    // members are duplicated
+    if let Some((header, body)) = split_container(&content)
+        && find_cfg_attrs(header.clone())?.is_empty()
+    {
+        let mut members = Group::new(Delimiter::Brace, expand_items(body.stream(), options));
+        members.set_span(body.span());
+        return Ok(quote!(#header #members));
     }
 
     let cfgs = find_base_cfgs(cfgs);
 
+    // All implication checks of this item share their caches
+    let mut solver = Solver::default();
+
     // Now construct every possible combination of applicable configurations
-    let configurations = generate_all_combinations(cfgs);
+    let Some(configurations) = generate_all_combinations(cfgs.clone(), options.limit, &mut solver)
+    else {
+        let names = cfgs
+            .iter()
+            .map(|cfg| format!("`{cfg}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Err(syn::Error::new_spanned(
+            content,
+            format!(
+                "too many configurations: the {} cfgs {names} produce more than {} combinations (up to 2^{}), \
+                 raise the limit with `#![cfg_tt(limit = N)]` at the start of the input or split it up",
+                cfgs.len(),
+                options.limit,
+                cfgs.len(),
+            ),
+        ));
+    };
 
-    let mut out = TokenStream::new();
-    for cfg in &configurations {
-        let expanded = expand_for_cfg(content.clone(), cfg);
-        let items = match syn::parse2::<Many<Item>>(expanded.clone()) {
-            Ok(items) => items.0.iter().map(|item| item.to_token_stream()).collect(),
-            Err(_) => match syn::parse2::<Many<Stmt>>(expanded.clone()) {
-                Ok(stmts) => stmts.0.iter().map(|item| item.to_token_stream()).collect(),
-                Err(_) => vec![expanded],
-            },
-        };
+    // Configurations that expand to the same tokens are only emitted once
+    let mut merged = Vec::<(Vec<Cfg>, TokenStream)>::new();
+    let mut seen = HashMap::<String, usize>::new();
+    for cfg in configurations {
+        let expanded = expand_for_cfg(content.clone(), &cfg, &mut solver);
+        match seen.entry(expanded.to_string()) {
+            Entry::Occupied(entry) => merged[*entry.get()].0.push(cfg),
+            Entry::Vacant(entry) => {
+                entry.insert(merged.len());
+                merged.push((vec![cfg], expanded));
+            }
+        }
+    }
+    // Reduce the predicates to the atoms that matter, an expansion that is the same in every
+    // configuration gets no #[cfg] at all
+    let expansions = merged
+        .into_iter()
+        .map(|(cfgs, expanded)| (Cfg::any_of(cfgs).simplify(), expanded))
+        .filter(|(cfg, _)| *cfg != Cfg::Any(Vec::new()))
+        .collect::<Vec<_>>();
+
+    // Every configuration has to expand to valid code, even the ones the host never compiles
+    for (cfg, expanded) in &expansions {
+        validate(cfg, expanded, &content)?;
+    }
+
+    // In expression position, or for the tail expression of a block, items and statements
+    // can't be emitted side by side, but the arms of a match can be cfg'd.
+    let is_expr = expansions.iter().all(|(_, expanded)| {
+        syn::parse2::<Many<Item>>(expanded.clone()).is_err()
+            && (syn::parse2::<Expr>(expanded.clone()).is_ok() || ends_with_expr(expanded))
+    });
+    if is_expr {
+        // the same in every configuration
+        if let [(Cfg::All(list), expanded)] = expansions.as_slice()
+            && list.is_empty()
+        {
+            return Ok(expanded.clone());
+        }
 
-        for item in items {
+        let arms = expansions.iter().map(|(cfg, expanded)| {
+            let cfg = cfg.to_token_stream();
+            if syn::parse2::<Expr>(expanded.clone()).is_ok() {
+                quote!(#cfg () => #expanded,)
+            } else {
+                // statements followed by the tail expression
+                quote!(#cfg () => { #expanded })
+            }
+        });
+        return Ok(quote!(match () { #(#arms)* }));
+    }
+
+    let mut out = TokenStream::new();
+    for (cfg, expanded) in expansions {
+        for item in split_parsed(expanded) {
             out.extend([cfg.to_token_stream(), item]);
         }
     }
 
-    out.into()
+    Ok(out)
+}
+
+/// Splits `expanded` into the items, statements or associated items it consists of, so that
+/// each of them can get a `#[cfg]`.
+fn split_parsed(expanded: TokenStream) -> Vec<TokenStream> {
+    fn parse<T: Parse + ToTokens>(ts: &TokenStream) -> Option<Vec<TokenStream>> {
+        let many = syn::parse2::<Many<T>>(ts.clone()).ok()?;
+        Some(many.0.iter().map(T::to_token_stream).collect())
+    }
+
+    fn parse_stmts(ts: &TokenStream) -> Option<Vec<TokenStream>> {
+        let stmts = Block::parse_within.parse2(ts.clone()).ok()?;
+        let stmts = stmts.iter().map(|stmt| match stmt {
+            // only some expressions may have attributes, but blocks always can
+            Stmt::Expr(Expr::Block(_), _) => stmt.to_token_stream(),
+            Stmt::Expr(..) => quote!({ #stmt }),
+            stmt => stmt.to_token_stream(),
+        });
+        Some(stmts.collect())
+    }
+
+    parse::<Item>(&expanded)
+        .or_else(|| parse_stmts(&expanded))
+        .or_else(|| parse::<ImplItem>(&expanded))
+        .or_else(|| parse::<TraitItem>(&expanded))
+        .unwrap_or_else(|| vec![expanded])
+}
+
+/// Returns whether `ts` are statements that end with a tail expression, like the body of a block.
+fn ends_with_expr(ts: &TokenStream) -> bool {
+    Block::parse_within
+        .parse2(ts.clone())
+        .is_ok_and(|stmts| matches!(stmts.last(), Some(Stmt::Expr(_, None))))
+}
+
+/// Checks that `expanded` is valid as items, statements or an expression.
+///
+/// Otherwise, the error names the configuration `cfg` and points at the cfg attributes of
+/// `content` that shaped it.
+fn validate(cfg: &Cfg, expanded: &TokenStream, content: &TokenStream) -> syn::Result<()> {
+    let err = match syn::parse2::<Many<Item>>(expanded.clone()) {
+        Ok(_) => return Ok(()),
+        Err(err) => err,
+    };
+    let is_valid = Block::parse_within.parse2(expanded.clone()).is_ok()
+        || syn::parse2::<Many<ImplItem>>(expanded.clone()).is_ok()
+        || syn::parse2::<Many<TraitItem>>(expanded.clone()).is_ok()
+        || syn::parse2::<Expr>(expanded.clone()).is_ok();
+    if is_valid {
+        return Ok(());
+    }
+
+    // Prefer the error of statements if they got further, e.g. for `let x = ...;`. Errors at
+    // the end of the input are spanned on the whole macro call, so they come last.
+    let at_end = |err: &syn::Error| err.to_string().starts_with("unexpected end of input");
+    let err = match Block::parse_within.parse2(expanded.clone()) {
+        Err(stmt_err) if at_end(&stmt_err) || stmt_err.span().start() > err.span().start() => {
+            stmt_err
+        }
+        _ => err,
+    };
+    let span = match expanded.clone().into_iter().last() {
+        Some(last) if at_end(&err) => last.span(),
+        _ => err.span(),
+    };
+
+    let configuration = match cfg {
+        Cfg::All(list) if list.is_empty() => "every configuration".to_string(),
+        cfg => format!("the configuration `{cfg}`"),
+    };
+    let mut error = syn::Error::new(span, format!("{err}, in {configuration}"));
+    for site in find_cfg_sites(content.clone(), &cfg.atoms()) {
+        error.combine(syn::Error::new_spanned(
+            site,
+            format!("{configuration} depends on this cfg"),
+        ));
+    }
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(s: &str) -> String {
+        let ts = s.parse::<TokenStream>().unwrap();
+        expand_item(ts, &Options::default()).unwrap().to_string()
+    }
+
+    #[test]
+    fn identical_expansions_are_merged() {
+        let out = expand("const F: i32 = #[cfg(a)] 1 #[cfg_else] { 2 #[cfg(b)] (+ 1) };");
+        assert_eq!(out.matches("const F").count(), 3);
+    }
+
+    #[test]
+    fn irrelevant_cfgs_are_merged() {
+        let out = expand("const F: i32 = #[cfg(a)] (1 +) #[cfg(b)] {} 2;");
+        assert_eq!(out.matches("const F").count(), 2);
+    }
+
+    #[test]
+    fn emitted_cfgs_are_minimized() {
+        let out = expand("const F: i32 = #[cfg(a)] (1 +) #[cfg(b)] {} 2;");
+        let expected = quote! {
+            #[cfg(not(a))] const F: i32 = 2;
+            #[cfg(a)] const F: i32 = 1 + 2;
+        };
+        assert_eq!(out, expected.to_string());
+    }
+
+    #[test]
+    fn only_affected_members_are_duplicated() {
+        let out = expand(
+            "impl S { const X: i32 = 1; fn a() {} mod m { const B: i32 = 1 #[cfg(a)] (+ 1); } }",
+        );
+        let expected = quote! {
+            impl S {
+                const X: i32 = 1;
+                fn a() {}
+                mod m {
+                    #[cfg(not(a))] const B: i32 = 1;
+                    #[cfg(a)] const B: i32 = 1 + 1;
+                }
+            }
+        };
+        assert_eq!(out, expected.to_string());
+    }
+
+    #[test]
+    fn only_affected_statements_are_duplicated() {
+        let out =
+            expand("fn f() -> i32 { let x = 1 #[cfg(a)] (+ 1); let y = 2; x + y #[cfg(b)] (+ 1) }");
+        let expected = quote! {
+            fn f() -> i32 {
+                #[cfg(not(a))] let x = 1;
+                #[cfg(a)] let x = 1 + 1;
+                let y = 2;
+                match () {
+                    #[cfg(not(b))] () => x + y,
+                    #[cfg(b)] () => x + y + 1,
+                }
+            }
+        };
+        assert_eq!(out, expected.to_string());
+    }
+
+    #[test]
+    fn only_constant_cfgs_expand_once() {
+        let out = expand("const F: i32 = 1 #[cfg(false)] (+ 1);");
+        assert_eq!(
+            out,
+            quote!(
+                const F: i32 = 1;
+            )
+            .to_string()
+        );
+        let out = expand("const F: i32 = 1 #[cfg(true)] (+ 1);");
+        assert_eq!(
+            out,
+            quote!(
+                const F: i32 = 1 + 1;
+            )
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn constant_cfgs_are_folded() {
+        let out = expand("const F: i32 = 1 #[cfg(a)] {} #[cfg(false)] (+ 1);");
+        assert_eq!(
+            out,
+            quote!(
+                const F: i32 = 1;
+            )
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn list_elements_end_at_their_comma() {
+        let out = expand("type T = F<#[cfg_elem(a)] G<A, B>, fn() -> C, #[cfg_elem(a)] H<I<J>>>;");
+        let expected = quote! {
+            #[cfg(not(a))] type T = F<fn() -> C,>;
+            #[cfg(a)] type T = F<G<A, B>, fn() -> C, H<I<J> > >;
+        };
+        assert_eq!(out, expected.to_string());
+    }
+
+    #[test]
+    fn native_cfgs_are_left_to_rustc() {
+        let input = "#[cfg(a)] struct S { #[cfg(b)] x: u8, y: #[cfg(c)] u8 #[cfg_else] i8 }";
+        let out = expand(input);
+        let expected = quote! {
+            #[cfg(not(c))] #[cfg(a)] struct S { #[cfg(b)] x: u8, y: i8 }
+            #[cfg(c)] #[cfg(a)] struct S { #[cfg(b)] x: u8, y: u8 }
+        };
+        assert_eq!(out, expected.to_string());
+    }
+
+    #[test]
+    fn named_elements_are_native_in_fields_and_params_only() {
+        // where clauses and macro arguments are expanded by cfg_tt
+        let input = "fn f<T, U>() where T: Clone, #[cfg(a)] U: Copy {}";
+        let out = expand_for_cfg(
+            input.parse().unwrap(),
+            &Cfg::All(Vec::new()),
+            &mut Solver::default(),
+        );
+        assert_eq!(
+            out.to_string(),
+            quote!(fn f<T, U>() where T: Clone, : Copy {}).to_string()
+        );
+        let out = expand("const F: i32 = kv!(a: 1, #[cfg(b)] b: 2);");
+        assert_eq!(out.matches("const F").count(), 2);
+        let out = expand("const F: i32 = kv! { a: 1, #[cfg(b)] b: 2 };");
+        assert_eq!(out.matches("const F").count(), 2);
+
+        let input = "fn f(#[cfg(a)] x: u8) -> S { S { #[cfg(b)] x: 1 } }";
+        assert_eq!(
+            expand(input),
+            input.parse::<TokenStream>().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn verbatim_regions_are_untouched() {
+        let out = expand("const F: i32 = m!(#[cfg_tt::verbatim] { #[cfg(a)] 1 }) #[cfg(b)] (+ 1);");
+        let expected = quote! {
+            #[cfg(not(b))] const F: i32 = m!({ #[cfg(a)] 1 });
+            #[cfg(b)] const F: i32 = m!({ #[cfg(a)] 1 }) + 1;
+        };
+        assert_eq!(out, expected.to_string());
+    }
+
+    #[test]
+    fn inner_cfgs_apply_to_their_group() {
+        let out = expand("fn f() { let x = 1; { #![cfg(a)] g(x); } }");
+        let expected = quote! {
+            fn f() {
+                let x = 1;
+                #[cfg(a)] { g(x); }
+            }
+        };
+        assert_eq!(out, expected.to_string());
+
+        // rustc supports them on modules itself
+        let input = "mod m { #![cfg(a)] const F: i32 = 1 #[cfg(b)] (+ 1); }";
+        let expected = quote! {
+            mod m {
+                #![cfg(a)]
+                #[cfg(not(b))] const F: i32 = 1;
+                #[cfg(b)] const F: i32 = 1 + 1;
+            }
+        };
+        // `#!` is only spaced differently
+        let unspaced = |s: String| s.replace(' ', "");
+        assert_eq!(unspaced(expand(input)), unspaced(expected.to_string()));
+    }
 }
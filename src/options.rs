@@ -0,0 +1,103 @@
+use crate::find::*;
+use proc_macro2::TokenStream;
+use syn::{
+    Attribute, Expr, ExprLit, ExprPath, Lit, Meta, Path, Token, parse::Parser, parse_quote,
+    punctuated::Punctuated,
+};
+
+/// The default maximum number of configurations a single item may be expanded for.
+const DEFAULT_LIMIT: usize = 1024;
+
+/// Options of a single invocation, given as `#![cfg_tt(...)]` at the start of the input of
+/// `cfg_tt!` or as the arguments of `#[cfg_tt_attr(...)]`.
+pub struct Options {
+    /// The maximum number of configurations a single item may be expanded for.
+    pub limit: usize,
+    /// Whether the cfgs are resolved by the compiler through a chain of `#[cfg]`'d macro calls, so
+    /// only the active configuration is expanded.
+    pub single_expansion: bool,
+    /// The path `cfg_tt` is available under, e.g. if the dependency is renamed or re-exported.
+    pub crate_path: Path,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            limit: DEFAULT_LIMIT,
+            single_expansion: false,
+            crate_path: parse_quote!(::cfg_tt),
+        }
+    }
+}
+
+impl Options {
+    /// Consumes the `#![cfg_tt(...)]` attributes at the front of `it` and applies their options.
+    pub fn take(&mut self, it: &mut TokenIter) -> syn::Result<()> {
+        while let Some(attr) = peek_options_attr(it) {
+            // consume #![cfg_tt(...)]
+            let _ = it.next();
+            let _ = it.next();
+            let _ = it.next();
+            self.parse_attr(&attr)?;
+        }
+        Ok(())
+    }
+
+    fn parse_attr(&mut self, attr: &Attribute) -> syn::Result<()> {
+        let Meta::List(list) = &attr.meta else {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "expected options like `#![cfg_tt(limit = 4096)]`",
+            ));
+        };
+        self.parse_args(list.tokens.clone())
+    }
+
+    /// Parses comma separated options like `limit = 4096` or `single_expansion`.
+    pub fn parse_args(&mut self, args: TokenStream) -> syn::Result<()> {
+        let items = Punctuated::<Meta, Token![,]>::parse_terminated.parse2(args)?;
+        for meta in items {
+            match &meta {
+                Meta::NameValue(nv) if nv.path.is_ident("limit") => {
+                    let Expr::Lit(ExprLit {
+                        lit: Lit::Int(lit), ..
+                    }) = &nv.value
+                    else {
+                        return Err(syn::Error::new_spanned(
+                            &nv.value,
+                            "expected the maximum number of configurations, like `limit = 4096`",
+                        ));
+                    };
+                    self.limit = lit.base10_parse()?;
+                }
+                Meta::Path(path) if path.is_ident("single_expansion") => {
+                    self.single_expansion = true;
+                }
+                Meta::NameValue(nv) if nv.path.is_ident("crate") => {
+                    let Expr::Path(ExprPath {
+                        path, qself: None, ..
+                    }) = &nv.value
+                    else {
+                        return Err(syn::Error::new_spanned(
+                            &nv.value,
+                            "expected the path of `cfg_tt`, like `crate = ::my_cfg_tt`",
+                        ));
+                    };
+                    self.crate_path = path.clone();
+                }
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        meta,
+                        "unknown `cfg_tt` option, expected `limit = N`, `single_expansion` or `crate = path`",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses the `#![cfg_tt(...)]` attribute at the front of `it` without consuming it.
+fn peek_options_attr(it: &TokenIter) -> Option<Attribute> {
+    peek_inner_attr(it).filter(|attr| attr.path().is_ident("cfg_tt"))
+}
@@ -0,0 +1,136 @@
+use crate::cfg::Cfg;
+use syn::{Expr, ExprLit, Lit, Meta};
+
+/// Keys that have exactly one value on any given target.
+const SINGLE_VALUED: &[&str] = &[
+    "target_os",
+    "target_arch",
+    "target_pointer_width",
+    "target_endian",
+    "target_env",
+    "target_abi",
+    "target_vendor",
+    "panic",
+];
+
+/// Families that never apply to the same target.
+const EXCLUSIVE_FAMILIES: &[&str] = &["unix", "windows"];
+
+/// Operating systems of the `unix` family.
+const UNIX_OSES: &[&str] = &[
+    "linux",
+    "android",
+    "macos",
+    "ios",
+    "tvos",
+    "watchos",
+    "visionos",
+    "freebsd",
+    "netbsd",
+    "openbsd",
+    "dragonfly",
+    "solaris",
+    "illumos",
+    "haiku",
+    "redox",
+    "aix",
+    "fuchsia",
+    "emscripten",
+];
+
+/// Operating systems with `target_vendor = "apple"`.
+const APPLE_OSES: &[&str] = &["macos", "ios", "tvos", "watchos", "visionos"];
+
+/// A `key = "value"` fact about the target, with `unix` and `windows` normalized to their
+/// `target_family`.
+#[derive(PartialEq)]
+struct Fact {
+    key: String,
+    value: String,
+}
+
+impl Fact {
+    fn from_meta(meta: &Meta) -> Option<Fact> {
+        match meta {
+            Meta::Path(path) if path.is_ident("unix") || path.is_ident("windows") => Some(Fact {
+                key: "target_family".into(),
+                value: path.get_ident()?.to_string(),
+            }),
+            Meta::NameValue(nv) => {
+                let Expr::Lit(ExprLit {
+                    lit: Lit::Str(value),
+                    ..
+                }) = &nv.value
+                else {
+                    return None;
+                };
+                Some(Fact {
+                    key: nv.path.get_ident()?.to_string(),
+                    value: value.value(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The value this fact implies for `key`, if any.
+    fn implied(&self, key: &str) -> Option<&'static str> {
+        if self.key != "target_os" {
+            return None;
+        }
+        let os = self.value.as_str();
+        match key {
+            "target_family" if UNIX_OSES.contains(&os) => Some("unix"),
+            "target_family" if os == "windows" => Some("windows"),
+            "target_vendor" if APPLE_OSES.contains(&os) => Some("apple"),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the relationships between `atoms` that hold on every target.
+///
+/// This covers keys like `target_os` that only have a single value, `unix` and `windows`
+/// excluding each other and operating systems implying their `target_family`.
+pub fn constraints(atoms: &[Meta]) -> Vec<Cfg> {
+    let facts = atoms
+        .iter()
+        .filter_map(|atom| Some((Cfg::Atomic(Box::new(atom.clone())), Fact::from_meta(atom)?)))
+        .collect::<Vec<_>>();
+    let implies = |a: &Cfg, b: &Cfg| Cfg::Any(vec![Cfg::Not(Box::new(a.clone())), b.clone()]);
+    let excludes = |a: &Cfg, b: &Cfg| Cfg::Not(Box::new(Cfg::All(vec![a.clone(), b.clone()])));
+
+    let mut out = Vec::new();
+    for (i, (a, fa)) in facts.iter().enumerate() {
+        for (b, fb) in &facts[i + 1..] {
+            if fa == fb {
+                // e.g. unix and target_family = "unix"
+                out.push(implies(a, b));
+                out.push(implies(b, a));
+            } else if fa.key == fb.key
+                && (SINGLE_VALUED.contains(&fa.key.as_str())
+                    || fa.key == "target_family"
+                        && EXCLUSIVE_FAMILIES.contains(&fa.value.as_str())
+                        && EXCLUSIVE_FAMILIES.contains(&fb.value.as_str()))
+            {
+                out.push(excludes(a, b));
+            }
+        }
+
+        for (b, fb) in &facts {
+            let Some(value) = fa.implied(&fb.key) else {
+                continue;
+            };
+            if fb.value == value {
+                out.push(implies(a, b));
+            } else if SINGLE_VALUED.contains(&fb.key.as_str())
+                || fb.key == "target_family"
+                    && EXCLUSIVE_FAMILIES.contains(&value)
+                    && EXCLUSIVE_FAMILIES.contains(&fb.value.as_str())
+            {
+                out.push(excludes(a, b));
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,131 @@
+use crate::{Many, bdd::Solver, cfg::Cfg, expand_for_cfg, find::*, find_base_cfgs};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    Expr, Item, LitBool, Path, Token, braced, bracketed, parenthesized,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+};
+
+/// Expands `content` by letting the compiler resolve every base cfg once, instead of emitting
+/// every configuration.
+///
+/// The first base cfg is resolved by a pair of `__cfg_tt_eval!` calls, one under the cfg and one
+/// under its negation. Each call appends the value of its cfg to its input and resolves the next
+/// one the same way. Once all values are collected, `content` is expanded for them only, so the
+/// output grows linearly with the number of cfgs. Unlike `macro_rules!` definitions, the calls
+/// are accepted in `impl` and `trait` bodies as well, and no names have to be made up.
+///
+/// The calls go through `crate_path`, under which `cfg_tt` is available to the caller.
+pub fn expand_single(content: TokenStream, crate_path: &Path) -> syn::Result<TokenStream> {
+    let cfgs = find_base_cfgs(find_cfg_attrs(content.clone())?);
+    if cfgs.is_empty() {
+        // only constant cfgs, if any
+        return Ok(expand_for_cfg(
+            content,
+            &Cfg::All(Vec::new()),
+            &mut Solver::default(),
+        ));
+    }
+    let out = resolve_next(crate_path, &cfgs, &content, &[]);
+
+    // In expression position, the calls have to be wrapped in a block. All configurations are
+    // assumed to agree on whether the input is an expression.
+    let none = Cfg::All(
+        cfgs.into_iter()
+            .map(|cfg| Cfg::Not(Box::new(cfg)))
+            .collect(),
+    );
+    let sample = expand_for_cfg(content, &none, &mut Solver::default());
+    if syn::parse2::<Many<Item>>(sample.clone()).is_err() && syn::parse2::<Expr>(sample).is_ok() {
+        return Ok(quote!({ #out }));
+    }
+    Ok(out)
+}
+
+/// Emits the pair of `#[cfg]`'d `__cfg_tt_eval!` calls that resolves the first cfg without a
+/// value yet.
+fn resolve_next(
+    crate_path: &Path,
+    cfgs: &[Cfg],
+    content: &TokenStream,
+    values: &[bool],
+) -> TokenStream {
+    let pred = cfgs[values.len()].to_cfg_meta();
+    let preds = cfgs.iter().map(Cfg::to_cfg_meta).collect::<Vec<_>>();
+    let call = |value: bool| {
+        let values = values.iter().chain([&value]);
+        quote! {
+            #crate_path::__cfg_tt_eval! { #crate_path; (#(#preds),*) { #content } #([#values])* }
+        }
+    };
+    let (active, inactive) = (call(true), call(false));
+    quote! {
+        #[cfg(#pred)] #active
+        #[cfg(not(#pred))] #inactive
+    }
+}
+
+/// The input of `__cfg_tt_eval!`: the path of `cfg_tt`, the base cfgs, the tokens to expand and
+/// the value of every base cfg in the active configuration, as in
+/// `::cfg_tt; (a, b) { ... } [true] [false]`.
+struct Eval {
+    crate_path: Path,
+    cfgs: Vec<Cfg>,
+    content: TokenStream,
+    values: Vec<bool>,
+}
+
+impl Parse for Eval {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let crate_path = input.parse()?;
+        input.parse::<Token![;]>()?;
+
+        let cfgs;
+        parenthesized!(cfgs in input);
+        let cfgs = Punctuated::<Cfg, Token![,]>::parse_terminated(&cfgs)?;
+
+        let content;
+        braced!(content in input);
+        let content = content.parse()?;
+
+        let mut values = Vec::new();
+        while !input.is_empty() {
+            let value;
+            bracketed!(value in input);
+            values.push(value.parse::<LitBool>()?.value);
+        }
+
+        Ok(Eval {
+            crate_path,
+            cfgs: cfgs.into_iter().collect(),
+            content,
+            values,
+        })
+    }
+}
+
+/// Resolves the next cfg of an [`expand_single`] chain, or expands its tokens once the values
+/// of all cfgs are collected.
+pub fn eval(input: TokenStream) -> syn::Result<TokenStream> {
+    let Eval {
+        crate_path,
+        cfgs,
+        content,
+        values,
+    } = syn::parse2(input)?;
+    if values.len() < cfgs.len() {
+        return Ok(resolve_next(&crate_path, &cfgs, &content, &values));
+    }
+
+    let active = cfgs
+        .into_iter()
+        .zip(values)
+        .map(|(cfg, value)| if value { cfg } else { Cfg::Not(Box::new(cfg)) })
+        .collect();
+    Ok(expand_for_cfg(
+        content,
+        &Cfg::All(active),
+        &mut Solver::default(),
+    ))
+}
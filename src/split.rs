@@ -0,0 +1,301 @@
+use crate::find::*;
+use proc_macro2::{Delimiter, Group, TokenStream, TokenTree};
+
+/// Keywords of items that end with a `{ ... }` body instead of a `;`.
+const BRACED_ITEMS: &[&str] = &[
+    "fn",
+    "struct",
+    "enum",
+    "union",
+    "trait",
+    "impl",
+    "mod",
+    "extern",
+    "macro_rules",
+];
+
+/// Splits `ts` into its top-level items (or statements), so that each of them can be
+/// expanded on its own.
+///
+/// An item ends at a top-level `;` or, for items like `fn` or `impl` and braced macro calls,
+/// at its `{ ... }` body. Tokens that only make sense together, like a `#[cfg_begin(...)]`
+/// range or a `#[cfg_else]` chain, are never split apart.
+pub fn split_items(ts: TokenStream) -> Vec<TokenStream> {
+    let mut it = TokenIter::new(ts);
+    let mut items = Vec::new();
+    let mut item = TokenStream::new();
+
+    // whether the current item ends with its body
+    let mut braced = false;
+    // whether the kind of the current item is still undecided
+    let mut in_header = true;
+    // whether only attributes, a visibility and qualifiers came before in the current item
+    let mut leading = true;
+    // whether the last token was an attribute, whose target never ends an item
+    let mut after_attr = false;
+    // depth of the #[cfg_begin(...)] ranges the current item is in
+    let mut ranges = 0usize;
+    // the last token of the current item
+    let mut last: Option<TokenTree> = None;
+
+    while let Some(tt) = it.next() {
+        // #![...] at the start of the input stays in front of everything else
+        if item.is_empty() && is_inner_attr(&tt, &it) {
+            let bang = it.next().unwrap();
+            let group = it.next().unwrap();
+            items.push(TokenStream::from_iter([tt, bang, group]));
+            continue;
+        }
+
+        if let Some(attr) = peek_attr(&tt, &it) {
+            if attr.path().is_ident("cfg_begin") {
+                ranges += 1;
+            } else if is_marker(&attr, "cfg_end") {
+                ranges = ranges.saturating_sub(1);
+            }
+
+            let group = it.next().unwrap();
+            item.extend([tt, group.clone()]);
+            after_attr = true;
+            last = Some(group);
+            continue;
+        }
+
+        // the keyword of an item, as opposed to a `fn` in the type of a `let` or `const`
+        let is_keyword = leading
+            && matches!(&tt, TokenTree::Ident(ident) if BRACED_ITEMS.iter().any(|kw| ident == kw));
+        leading &= match &tt {
+            TokenTree::Ident(ident) => ident == "pub" || QUALIFIERS.iter().any(|kw| ident == kw),
+            // pub(crate)
+            TokenTree::Group(g) => {
+                g.delimiter() == Delimiter::Parenthesis
+                    && matches!(&last, Some(TokenTree::Ident(ident)) if ident == "pub")
+            }
+            _ => false,
+        };
+
+        let ends = match &tt {
+            TokenTree::Punct(p) if p.as_char() == ';' => !after_attr,
+            TokenTree::Punct(p) if p.as_char() == '=' => {
+                in_header = false;
+                false
+            }
+            TokenTree::Punct(p) if p.as_char() == '!' && in_header => {
+                // path! { ... }
+                let is_macro = matches!(last, Some(TokenTree::Ident(_)))
+                    && matches!(it.peek(), Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace);
+                if is_macro {
+                    braced = true;
+                    in_header = false;
+                }
+                false
+            }
+            TokenTree::Ident(_) if in_header && is_keyword => {
+                braced = true;
+                in_header = false;
+                false
+            }
+            TokenTree::Group(g) if g.delimiter() == Delimiter::Brace => {
+                in_header = false;
+                braced && !after_attr
+            }
+            _ => false,
+        };
+
+        item.extend([tt.clone()]);
+        after_attr = false;
+        last = Some(tt);
+
+        let continues = ranges > 0
+            || peek_next_attr(&it).is_some_and(|attr| {
+                is_marker(&attr, "cfg_else") || attr.path().is_ident("cfg_else_if")
+            });
+        if ends && !continues {
+            items.push(std::mem::take(&mut item));
+            braced = false;
+            in_header = true;
+            leading = true;
+            last = None;
+        }
+    }
+
+    if !item.is_empty() {
+        items.push(item);
+    }
+    items
+}
+
+/// Keywords of items whose `{ ... }` body consists of further items or statements.
+const CONTAINERS: &[&str] = &["fn", "impl", "trait", "mod", "extern"];
+
+/// Qualifiers that may precede the keyword of an item.
+const QUALIFIERS: &[&str] = &["unsafe", "safe", "async", "const", "default", "auto"];
+
+/// Splits a function or an `impl`, `trait`, `mod` or `extern` block into its header and its
+/// `{ ... }` body.
+pub fn split_container(item: &TokenStream) -> Option<(TokenStream, Group)> {
+    let mut tokens = item.clone().into_iter().collect::<Vec<_>>();
+    let Some(TokenTree::Group(body)) = tokens.pop() else {
+        return None;
+    };
+    if body.delimiter() != Delimiter::Brace {
+        return None;
+    }
+
+    is_container_header(&tokens).then(|| (tokens.into_iter().collect(), body))
+}
+
+/// Returns whether `header` starts a function or an `impl`, `trait`, `mod` or `extern` block,
+/// judging by its keyword after any attributes, visibility and qualifiers.
+pub fn is_container_header(header: &[TokenTree]) -> bool {
+    let mut it = header.iter().peekable();
+    while let Some(tt) = it.next() {
+        match tt {
+            // #[...]
+            TokenTree::Punct(p) if p.as_char() == '#' => {
+                it.next();
+            }
+            TokenTree::Ident(ident) if ident == "pub" => {
+                // pub(crate)
+                it.next_if(|tt| matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Parenthesis));
+            }
+            TokenTree::Ident(ident) if ident == "extern" => {
+                // extern "C" fn or extern "C" { ... }
+                it.next_if(|tt| matches!(tt, TokenTree::Literal(_)));
+                if it.peek().is_none() {
+                    return true;
+                }
+            }
+            TokenTree::Ident(ident) if QUALIFIERS.iter().any(|kw| ident == kw) => {}
+            TokenTree::Ident(ident) => return CONTAINERS.iter().any(|kw| ident == kw),
+            _ => return false,
+        }
+    }
+    false
+}
+
+fn is_inner_attr(pound: &TokenTree, it: &TokenIter) -> bool {
+    matches!(pound, TokenTree::Punct(p) if p.as_char() == '#')
+        && matches!(it.peek(), Some(TokenTree::Punct(p)) if p.as_char() == '!')
+        && matches!(it.peek_nth(1), Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(s: &str) -> Vec<String> {
+        let ts = s.parse::<TokenStream>().unwrap();
+        split_items(ts).iter().map(|ts| ts.to_string()).collect()
+    }
+
+    fn normalize(s: &str) -> String {
+        s.parse::<TokenStream>().unwrap().to_string()
+    }
+
+    #[test]
+    fn splits_items() {
+        let items = split(
+            "fn a() -> i32 { 1 } struct S; struct T { x: u8 } const X: S = S { }; impl<T> A for T where T: Iterator<Item = u8> {}",
+        );
+        assert_eq!(
+            items,
+            [
+                normalize("fn a() -> i32 { 1 }"),
+                normalize("struct S;"),
+                normalize("struct T { x: u8 }"),
+                normalize("const X: S = S { };"),
+                normalize("impl<T> A for T where T: Iterator<Item = u8> {}"),
+            ]
+        );
+
+        // a `fn` in the type doesn't make the item end with its initializer
+        let items = split(
+            "pub static F: fn() -> i32 = { fn g() -> i32 { 1 } g }; pub(crate) unsafe extern \"C\" fn h() {} struct S;",
+        );
+        assert_eq!(
+            items,
+            [
+                normalize("pub static F: fn() -> i32 = { fn g() -> i32 { 1 } g };"),
+                normalize("pub(crate) unsafe extern \"C\" fn h() {}"),
+                normalize("struct S;"),
+            ]
+        );
+    }
+
+    #[test]
+    fn containers_are_told_by_their_keyword() {
+        let header = |s: &str| {
+            s.parse::<TokenStream>()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        assert!(is_container_header(&header(
+            "#[inline] pub(crate) const unsafe fn f()"
+        )));
+        assert!(is_container_header(&header("unsafe impl<T> Send for S<T>")));
+        assert!(is_container_header(&header("pub unsafe extern \"C\"")));
+        assert!(is_container_header(&header("extern \"C\" fn f()")));
+        assert!(!is_container_header(&header("struct S<T = fn()>")));
+        assert!(!is_container_header(&header("const X: fn() =")));
+    }
+
+    #[test]
+    fn splits_statements() {
+        let items = split("let x = if a { 1 } else { 2 }; foo! { } bar!(); x + 1");
+        assert_eq!(
+            items,
+            [
+                normalize("let x = if a { 1 } else { 2 };"),
+                normalize("foo! { }"),
+                normalize("bar!();"),
+                normalize("x + 1"),
+            ]
+        );
+
+        let items = split("let f: fn() -> i32 = if c { a } else { b }; f()");
+        assert_eq!(
+            items,
+            [
+                normalize("let f: fn() -> i32 = if c { a } else { b };"),
+                normalize("f()"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_cfg_targets_together() {
+        let items = split(
+            "#[cfg(a)] fn f() {} #[cfg_else] fn g() {} fn h() #[cfg(a)] { 1 } #[cfg(not(a))] { 2 }",
+        );
+        assert_eq!(
+            items,
+            [
+                normalize("#[cfg(a)] fn f() {} #[cfg_else] fn g() {}"),
+                normalize("fn h() #[cfg(a)] { 1 } #[cfg(not(a))] { 2 }"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_ranges_together() {
+        let items = split("#[cfg_begin(a)] fn f() {} #[cfg_end] fn g() {} fn h() {}");
+        assert_eq!(
+            items,
+            [
+                normalize("#[cfg_begin(a)] fn f() {} #[cfg_end] fn g() {}"),
+                normalize("fn h() {}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_off_inner_attrs() {
+        let items = split("#![allow(dead_code)] fn f() {}");
+        assert_eq!(
+            items,
+            [normalize("#![allow(dead_code)]"), normalize("fn f() {}")]
+        );
+    }
+}
@@ -0,0 +1,13 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub fn f() -> i32 {
+        1 #[cfg(windows)]
+    }
+
+    pub fn g() -> i32 {
+        #[cfg(unix)] 1 #[cfg_else] #[cfg(windows)]
+    }
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub fn a() -> i32 { 1 #[cfg(unix, windows)] (+ 1) }
+    pub fn b() -> i32 { 1 #[cfg()] (+ 1) }
+    pub fn c() -> i32 { 1 #[cfg(not())] (+ 1) }
+    pub fn d() -> i32 { 1 #[cfg(not(unix, windows))] (+ 1) }
+    pub fn e() -> i32 { 1 #[cfg(1 + 1)] (+ 1) }
+    pub fn f() -> i32 { 1 #[cfg(version("1.0"))] (+ 1) }
+    pub fn g() -> i32 { 1 #[cfg_attr(feature = 1, inline)] (+ 1) }
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    #![cfg_tt(limit = 4)]
+
+    pub fn f() -> i32 {
+        1 #[cfg(a)] (+ 1) #[cfg(b)] (+ 2) #[cfg(c)] (+ 3)
+    }
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub fn f() -> i32 {
+        1 #[cfg_begin(windows)] + 1
+    }
+}
+
+fn main() {}
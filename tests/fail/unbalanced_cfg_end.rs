@@ -0,0 +1,9 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub fn f() -> i32 {
+        1 + 1 #[cfg_end]
+    }
+}
+
+fn main() {}
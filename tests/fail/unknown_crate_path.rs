@@ -0,0 +1,11 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    #![cfg_tt(single_expansion, crate = ::not_cfg_tt)]
+
+    pub fn f() -> i32 {
+        1 #[cfg(unix)] (+ 1)
+    }
+}
+
+fn main() {}
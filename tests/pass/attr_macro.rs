@@ -0,0 +1,78 @@
+use cfg_tt::cfg_tt_attr;
+
+#[cfg_tt_attr]
+pub fn f() -> i32 {
+    let x = 1;
+    #[cfg(windows)] {
+        x + 1
+    }
+    #[cfg_else] {
+        x
+    }
+}
+
+#[cfg_tt_attr]
+#[derive(Debug, Default)]
+#[cfg_attr(windows, derive(Clone))]
+pub struct S {
+    x: u32,
+}
+
+#[cfg_tt_attr]
+#[cfg_attr(unix, repr(u8))]
+pub enum E {
+    A = 1,
+}
+
+#[cfg_tt_attr(limit = 4)]
+impl S {
+    pub fn x(&self) -> u32 {
+        #[cfg(unix)] {
+            self.x + 1
+        }
+        #[cfg_else] {
+            self.x
+        }
+    }
+}
+
+#[cfg_tt_attr]
+pub trait T {
+    fn g(#[cfg_attr(windows, allow(unused_variables))] a: u8) -> i32 {
+        #[cfg_begin(unix)]
+        let a = a + 1;
+        #[cfg_end]
+        a.into()
+    }
+}
+
+#[cfg_tt_attr]
+mod m {
+    pub fn g() -> i32 {
+        #[cfg(unix)] {
+            2
+        }
+        #[cfg_else] {
+            3
+        }
+    }
+}
+
+impl T for S {}
+
+fn main() {
+    #[cfg(unix)]
+    {
+        assert_eq!(f(), 1);
+        assert_eq!(E::A as u8, 1);
+        assert_eq!(S::default().x(), 1);
+        assert_eq!(S::g(1), 2);
+        assert_eq!(m::g(), 2);
+    }
+    #[cfg(windows)]
+    {
+        assert_eq!(f(), 2);
+        assert_eq!(S::g(1), 1);
+        let _ = S::default().clone();
+    }
+}
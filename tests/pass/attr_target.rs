@@ -0,0 +1,38 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    #[cfg(not(windows))] #[derive(Debug)]
+    #[cfg(windows)] #[derive(PartialEq)]
+    pub struct S;
+
+    #[cfg_attr(not(windows), derive(Clone))]
+    pub struct T;
+
+    // the attributes apply to the following tokens, like written out
+    #[cfg_attr(all(), cfg(unix))]
+    fn h() -> i32 { 1 }
+    #[cfg_attr(all(), cfg(not(unix)))]
+    fn h() -> i32 { 2 }
+
+    pub const N: i32 = 1
+        #[cfg_attr(all(), cfg_attr(all(), cfg(false)))] (+ 1)
+        + #[cfg_attr(any(), cfg(false))] 2;
+
+    pub fn f() -> i32 {
+        let g = |#[cfg_attr(not(windows), allow(unused_variables))] x: i32| 1;
+        g(0)
+    }
+}
+
+fn main() {
+    #[cfg(not(windows))]
+    {
+        let _ = format!("{:?}", S);
+        let _ = T.clone();
+    }
+    #[cfg(windows)]
+    assert!(S == S);
+    assert_eq!(f(), 1);
+    assert_eq!(h(), if cfg!(unix) { 1 } else { 2 });
+    assert_eq!(N, 3);
+}
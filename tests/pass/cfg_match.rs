@@ -0,0 +1,27 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub fn f() -> &'static str {
+        #[cfg_match] {
+            windows => { "windows" },
+            any(unix, target_os = "wasi") => "unix-like",
+            _ => { "other" }
+        }
+    }
+
+    pub fn g(a: i32, b: i32) -> i32 {
+        a #[cfg_match] { windows => -, _ => + } b
+    }
+}
+
+fn main() {
+    #[cfg(windows)]
+    {
+        assert_eq!(f(), "windows");
+        assert_eq!(g(2, 1), 1);
+    }
+    #[cfg(any(unix, target_os = "wasi"))]
+    assert_eq!(f(), "unix-like");
+    #[cfg(not(windows))]
+    assert_eq!(g(2, 1), 3);
+}
@@ -0,0 +1,30 @@
+use cfg_tt::cfg_tt;
+
+trait A { fn a(&self) -> i32 { 1 } }
+trait B { fn b(&self) -> i32 { 2 } }
+
+struct S;
+impl A for S {}
+impl B for S {}
+
+cfg_tt! {
+    pub fn f<T>(t: &T) -> i32
+    where
+        T: A #[cfg_begin(not(windows))] + B #[cfg_begin(windows)] + Send #[cfg_end] #[cfg_end]
+    {
+        #[cfg_begin(not(windows))]
+        let x = t.a() + t.b();
+        #[cfg_end]
+        #[cfg_begin(windows)]
+        let x = t.a();
+        #[cfg_end]
+        x
+    }
+}
+
+fn main() {
+    #[cfg(not(windows))]
+    assert_eq!(f(&S), 3);
+    #[cfg(windows)]
+    assert_eq!(f(&S), 1);
+}
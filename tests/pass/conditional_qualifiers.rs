@@ -0,0 +1,36 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    // a #[cfg] in front of a visibility or qualifier applies to just that token, the items exist
+    // in every configuration
+    #[cfg(windows)] pub fn f() -> i32 { 1 }
+    #[cfg(windows)] const fn g() -> i32 { 2 }
+    #[cfg(not(windows))] unsafe fn h() -> i32 { 3 }
+
+    unsafe fn raw() -> i32 { 1 }
+
+    // unsafe blocks and variables named like contextual keywords are expressions
+    fn a() -> i32 {
+        #[cfg(unix)] unsafe { raw() } #[cfg_else] { 2 }
+    }
+
+    fn b() -> i32 {
+        let default = 1;
+        #[cfg(unix)] default #[cfg_else] 2
+    }
+
+    fn c() -> i32 {
+        let union = 1;
+        #[cfg(not(unix))] union #[cfg_else] 2
+    }
+}
+
+fn main() {
+    #[allow(unused_unsafe)]
+    let h = unsafe { h() };
+    assert_eq!((f(), g(), h), (1, 2, 3));
+    #[cfg(unix)]
+    assert_eq!((a(), b(), c()), (1, 1, 2));
+    #[cfg(not(unix))]
+    assert_eq!((a(), b(), c()), (2, 2, 1));
+}
@@ -0,0 +1,30 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub fn f() -> i32 {
+        #[cfg(windows)] 1 #[cfg_else_if(unix)] 2 #[cfg_else] 3
+    }
+
+    pub fn g(a: i32, b: i32) -> i32 {
+        a #[cfg(windows)] - #[cfg_else] { + } b
+    }
+
+    pub fn h() -> i32 {
+        1 #[cfg(any())] (+ 10) #[cfg_else_if(any())] (+ 20)
+    }
+}
+
+fn main() {
+    #[cfg(windows)]
+    {
+        assert_eq!(f(), 1);
+        assert_eq!(g(2, 1), 1);
+    }
+    #[cfg(unix)]
+    assert_eq!(f(), 2);
+    #[cfg(not(any(windows, unix)))]
+    assert_eq!(f(), 3);
+    #[cfg(not(windows))]
+    assert_eq!(g(2, 1), 3);
+    assert_eq!(h(), 1);
+}
@@ -0,0 +1,26 @@
+use cfg_tt::cfg_tt;
+
+pub fn f() -> i32 {
+    let x = cfg_tt!(1 #[cfg(not(windows))] (+ 1));
+    x * 10
+}
+
+pub fn g() -> i32 {
+    cfg_tt! {
+        2 #[cfg(windows)] (* 3) #[cfg(not(windows))] (* 4)
+    }
+}
+
+fn main() {
+    #[cfg(not(windows))]
+    {
+        assert_eq!(f(), 20);
+        assert_eq!(g(), 8);
+    }
+    #[cfg(windows)]
+    {
+        assert_eq!(f(), 10);
+        assert_eq!(g(), 6);
+    }
+    assert!(cfg_tt!(#[cfg(windows)] true #[cfg_else] { 1 == 1 }));
+}
@@ -0,0 +1,50 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    #![cfg(not(windows))]
+
+    // only exists when the input's #![cfg] holds
+    pub fn unix_only() -> i32 {
+        1 #[cfg(target_pointer_width = "64")] (+ 1)
+    }
+}
+
+cfg_tt! {
+    // options and cfgs in any order
+    #![cfg(not(windows))]
+    #![cfg_tt(limit = 2)]
+    #![cfg(unix)]
+
+    pub fn unix_only_2() -> i32 {
+        2 #[cfg(target_pointer_width = "64")] (+ 1)
+    }
+}
+
+cfg_tt! {
+    pub fn f() -> i32 {
+        let #[cfg(windows)] mut x = 3;
+        // the whole group, including its delimiters, is conditional
+        { #![cfg(windows)] x += 10; }
+        x
+    }
+
+    // inner attributes of functions and modules are left to rustc
+    pub mod m {
+        #![cfg(windows)]
+        pub fn g() -> i32 { 1 }
+    }
+
+    pub fn h() -> i32 {
+        #![cfg_attr(windows, allow(unused))]
+        #[cfg(windows)] { 1 } #[cfg_else] { 2 }
+    }
+}
+
+fn main() {
+    #[cfg(windows)]
+    assert_eq!((f(), m::g(), h()), (13, 1, 1));
+    #[cfg(not(windows))]
+    assert_eq!((f(), unix_only() > 0, h()), (3, true, 2));
+    #[cfg(unix)]
+    assert!(unix_only_2() > 1);
+}
@@ -0,0 +1,35 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    // `::`, `+=` and `..=` are removed as a whole
+    pub fn path() -> i32 {
+        #[cfg(windows)] :: core::cmp::max(1, 2)
+    }
+
+    pub fn compound() -> i32 {
+        let mut x = 1;
+        x #[cfg(windows)] -= #[cfg(not(windows))] += 2;
+        x
+    }
+
+    pub fn range() -> i32 {
+        (0 #[cfg(windows)] .. #[cfg(not(windows))] ..= 3).sum()
+    }
+
+    // so are lifetimes and negative literals
+    pub fn first<#[cfg(not(windows))] 'a>(s: &#[cfg(not(windows))] 'a str) -> &#[cfg(not(windows))] 'a str {
+        &s[..1]
+    }
+
+    pub fn negative() -> i32 {
+        #[cfg(windows)] -1 #[cfg(not(windows))] -2
+    }
+}
+
+fn main() {
+    assert_eq!(first("ab"), "a");
+    #[cfg(windows)]
+    assert_eq!((path(), compound(), range(), negative()), (2, -1, 3, -1));
+    #[cfg(not(windows))]
+    assert_eq!((path(), compound(), range(), negative()), (2, 3, 6, -2));
+}
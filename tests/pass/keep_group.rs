@@ -0,0 +1,26 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub fn f() -> (i32, i32) {
+        let t = #[cfg_keep(not(windows))] (1, 2) #[cfg_keep(windows)] (3, 4);
+        let _block = #[cfg_keep(not(windows))] { t.0 } #[cfg_keep(windows)] { t.1 };
+        t
+    }
+
+    pub fn g() -> [u8; 2] {
+        #[cfg(not(windows))] ([1, 2]) #[cfg(windows)] ([3, 4])
+    }
+}
+
+fn main() {
+    #[cfg(not(windows))]
+    {
+        assert_eq!(f(), (1, 2));
+        assert_eq!(g(), [1, 2]);
+    }
+    #[cfg(windows)]
+    {
+        assert_eq!(f(), (3, 4));
+        assert_eq!(g(), [3, 4]);
+    }
+}
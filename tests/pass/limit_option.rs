@@ -0,0 +1,21 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    #![cfg_tt(limit = 2)]
+
+    // the limit applies to every item on its own
+    pub fn f() -> i32 {
+        1 #[cfg(windows)] (+ 1)
+    }
+
+    pub fn g() -> i32 {
+        2 #[cfg(unix)] (+ 1)
+    }
+}
+
+fn main() {
+    #[cfg(windows)]
+    assert_eq!(f(), 2);
+    #[cfg(unix)]
+    assert_eq!(g(), 3);
+}
@@ -0,0 +1,38 @@
+use cfg_tt::cfg_tt;
+
+pub struct Pair<A, B = ()>(A, B);
+
+cfg_tt! {
+    // the element is removed together with its comma
+    pub type P = Pair<#[cfg_elem(windows)] u16, u32>;
+
+    pub struct T(#[cfg_elem(windows)] Vec<(u8, u16)>, u32);
+
+    pub fn f<T>(t: T) -> usize
+    where
+        #[cfg_elem(not(windows))] T: Clone + Into<u64>,
+        T: Copy,
+    {
+        let _ = t;
+        sum(#[cfg_elem(windows)] 1, 2, #[cfg_elem(not(windows))] 3)
+    }
+
+    pub fn sum(#[cfg_elem(windows)] a: usize, b: usize, #[cfg_elem(not(windows))] c: usize) -> usize {
+        b #[cfg(windows)] (+ a) #[cfg(not(windows))] (+ c)
+    }
+}
+
+fn main() {
+    #[cfg(windows)]
+    {
+        let _: Pair<u16, u32> = Pair(0, 0);
+        let _ = T(vec![(1, 2)], 3);
+        assert_eq!(f(1u8), 3);
+    }
+    #[cfg(not(windows))]
+    {
+        let _: Pair<u32> = Pair(0, ());
+        let _ = T(3);
+        assert_eq!(f(1u8), 5);
+    }
+}
@@ -0,0 +1,17 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    // the predicate is only known once gated! is called
+    macro_rules! gated {
+        ($p:meta, $i:item) => {
+            #[cfg($p)] $i
+        };
+    }
+
+    gated!(unix, pub fn f() -> i32 { 1 });
+    gated!(not(unix), pub fn f() -> i32 { 2 });
+}
+
+fn main() {
+    assert_eq!(f(), if cfg!(unix) { 1 } else { 2 });
+}
@@ -0,0 +1,58 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    // native cfgs are left to rustc and don't add configurations
+    #![cfg_tt(limit = 2)]
+
+    #[derive(Debug, PartialEq)]
+    pub enum E {
+        #[cfg(windows)] W(u16),
+        #[cfg(not(windows))] U(u32),
+    }
+
+    #[repr(u8)]
+    pub enum D {
+        #[cfg(windows)] A = 1,
+        #[cfg(not(windows))] A = 2,
+        B = 3,
+    }
+
+    pub struct S {
+        #[cfg(windows)] w: u16,
+        #[cfg(not(windows))] u: u32,
+        pub x: #[cfg(unix)] u8 #[cfg_else] i8,
+    }
+
+    #[cfg(windows)]
+    fn f(#[cfg(windows)] a: u16, #[cfg(unix)] b: u8) -> E {
+        E::W(a)
+    }
+
+    #[cfg(not(windows))]
+    fn f(#[cfg(windows)] a: u16, #[cfg(unix)] b: u8) -> E {
+        let _ = b;
+        #[cfg(unix)]
+        let e = E::U(1);
+        #[cfg(not(unix))]
+        let e = E::U(2);
+        match e {
+            #[cfg(windows)]
+            E::W(_) => unreachable!(),
+            e => e,
+        }
+    }
+}
+
+fn main() {
+    assert_eq!((D::A as u8, D::B as u8), (if cfg!(windows) { 1 } else { 2 }, 3));
+    #[cfg(windows)]
+    {
+        let _ = S { w: 1, x: 0 };
+        assert_eq!(f(1), E::W(1));
+    }
+    #[cfg(unix)]
+    {
+        let _ = S { u: 1, x: 0 };
+        assert_eq!(f(1), E::U(1));
+    }
+}
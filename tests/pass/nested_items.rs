@@ -0,0 +1,67 @@
+use cfg_tt::cfg_tt;
+
+pub struct S;
+
+cfg_tt! {
+    impl S {
+        pub const X: i32 = 1;
+
+        pub fn f(&self) -> i32 {
+            Self::X #[cfg(windows)] (+ 1)
+        }
+    }
+
+    pub trait T {
+        type Out;
+
+        fn g(&self) -> Self::Out;
+
+        fn h(&self) -> i32 {
+            2 #[cfg(unix)] (* 2)
+        }
+    }
+
+    pub mod m {
+        #![allow(dead_code)]
+
+        pub fn i() -> i32 {
+            #[cfg(unix)] 3 #[cfg_else] 4
+        }
+
+        fn unused() {}
+    }
+
+    // not a container, even though `fn` appears in its header
+    pub struct F<T = fn()> {
+        pub t: T,
+        pub x: #[cfg(windows)] u8 #[cfg(not(windows))] u16,
+    }
+
+    pub unsafe extern "C" fn j() -> i32 {
+        #[cfg(unix)] 5 #[cfg_else] 6
+    }
+}
+
+impl T for S {
+    type Out = ();
+
+    fn g(&self) {}
+}
+
+fn main() {
+    S.g();
+    let _ = F::<fn()> { t: main, x: 0 };
+    assert!(matches!(unsafe { j() }, 5 | 6));
+    #[cfg(unix)]
+    {
+        assert_eq!(S.f(), 1);
+        assert_eq!(S.h(), 4);
+        assert_eq!(m::i(), 3);
+    }
+    #[cfg(windows)]
+    {
+        assert_eq!(S.f(), 2);
+        assert_eq!(S.h(), 2);
+        assert_eq!(m::i(), 4);
+    }
+}
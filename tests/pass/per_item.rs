@@ -0,0 +1,37 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub struct Plain(pub i32);
+
+    pub fn a() -> i32 {
+        1 #[cfg(not(windows))] (+ 1)
+    }
+
+    pub fn b() -> i32 {
+        10 #[cfg(target_pointer_width = "64")] (+ 10)
+    }
+
+    impl Plain {
+        pub fn get(&self) -> i32 {
+            self.0
+        }
+    }
+
+    pub const C: Plain = Plain { 0: #[cfg(windows)] 3 #[cfg_else] 4 };
+}
+
+fn main() {
+    assert_eq!(Plain(5).get(), 5);
+    #[cfg(not(windows))]
+    {
+        assert_eq!(a(), 2);
+        assert_eq!(C.get(), 4);
+    }
+    #[cfg(windows)]
+    {
+        assert_eq!(a(), 1);
+        assert_eq!(C.get(), 3);
+    }
+    #[cfg(target_pointer_width = "64")]
+    assert_eq!(b(), 20);
+}
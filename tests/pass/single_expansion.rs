@@ -0,0 +1,74 @@
+use cfg_tt::{cfg_tt, cfg_tt_attr};
+
+cfg_tt! {
+    #![cfg_tt(single_expansion)]
+
+    pub fn bits() -> u32 {
+        0
+        #[cfg(target_pointer_width = "64")] (+ 64)
+        #[cfg(target_pointer_width = "32")] (+ 32)
+        #[cfg(debug_assertions)] (* 1)
+        #[cfg(unix)] (+ 0)
+        #[cfg(windows)] (- 0)
+        #[cfg(target_endian = "little")] (+ 0)
+        #[cfg(panic = "unwind")] (+ 0)
+        #[cfg(target_has_atomic = "64")] (+ 0)
+        #[cfg(target_has_atomic = "32")] (+ 0)
+        #[cfg(target_has_atomic = "16")] (+ 0)
+        #[cfg(target_has_atomic = "8")] (+ 0)
+    }
+
+    pub struct S {
+        pub x: #[cfg(windows)] u16 #[cfg_else] u32,
+    }
+}
+
+#[cfg_tt_attr(single_expansion)]
+pub fn f() -> i32 {
+    #[cfg(windows)] { 1 }
+    #[cfg_else] { 2 }
+}
+
+pub trait T {
+    cfg_tt! {
+        #![cfg_tt(single_expansion)]
+        fn t(&self) -> i32 { #[cfg(windows)] 1 #[cfg_else] 2 }
+    }
+}
+
+// in impl and trait bodies
+impl T for S {}
+
+impl S {
+    cfg_tt! {
+        #![cfg_tt(single_expansion)]
+        pub fn g(&self) -> i32 { #[cfg(windows)] 1 #[cfg_else] 2 }
+    }
+
+    #[cfg_tt_attr(single_expansion)]
+    pub fn h(&self) -> i32 {
+        #[cfg(windows)] { 1 }
+        #[cfg_else] { 2 }
+    }
+}
+
+fn main() {
+    // 12 cfgs would exceed the default limit of 1024 configurations
+    assert_eq!(bits(), usize::BITS);
+
+    // in expression position
+    let x = cfg_tt!(#![cfg_tt(single_expansion)] 1 #[cfg(windows)] (+ 1));
+
+    // bindings of statements stay visible
+    cfg_tt! {
+        #![cfg_tt(single_expansion)]
+        let y = #[cfg(windows)] 1 #[cfg_else] 2;
+    }
+
+    let s = S { x: 0 };
+    #[cfg(windows)]
+    assert_eq!((x, y, f(), size_of_val(&s.x)), (2, 1, 1, 2));
+    #[cfg(not(windows))]
+    assert_eq!((x, y, f(), size_of_val(&s.x)), (1, 2, 2, 4));
+    assert_eq!((s.g(), s.h(), s.t()), (f(), f(), f()));
+}
@@ -0,0 +1,14 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub fn f() -> i32 {
+        1 #[cfg(not(windows))] #[cfg(not(windows))] (+ 1) #[cfg(windows)] #[cfg(not(windows))] (+ 2)
+    }
+}
+
+fn main() {
+    #[cfg(not(windows))]
+    assert_eq!(f(), 2);
+    #[cfg(windows)]
+    assert_eq!(f(), 1);
+}
@@ -0,0 +1,20 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub fn f() -> i32 {
+        let mut x = 1 #[cfg(windows)] (+ 1);
+        x #[cfg(unix)] (+=) #[cfg(not(unix))] (-=) 1;
+        let y = #[cfg(unix)] { x * 2 } #[cfg_else] { x };
+        if y > 0 {
+            x += 1;
+        }
+        x + y #[cfg(unix)] (+ 10)
+    }
+}
+
+fn main() {
+    #[cfg(unix)]
+    assert_eq!(f(), 17);
+    #[cfg(windows)]
+    assert_eq!(f(), 3);
+}
@@ -0,0 +1,23 @@
+use cfg_tt::cfg_tt;
+
+cfg_tt! {
+    pub fn os() -> &'static str {
+        #[cfg(target_os = "linux")] "linux"
+        #[cfg(target_os = "macos")] "macos"
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))] "other"
+    }
+}
+
+fn main() {
+    // all(unix, windows) is impossible, so `1 2` is never produced
+    let family = cfg_tt!(#[cfg(unix)] 1 #[cfg(windows)] 2 #[cfg(not(any(unix, windows)))] 3);
+
+    #[cfg(unix)]
+    assert_eq!(family, 1);
+    #[cfg(windows)]
+    assert_eq!(family, 2);
+    #[cfg(target_os = "linux")]
+    assert_eq!(os(), "linux");
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    assert_eq!(os(), "other");
+}
@@ -0,0 +1,19 @@
+use cfg_tt::cfg_tt;
+
+// interprets the attributes itself, so they must reach it unchanged
+macro_rules! count_attrs {
+    ({ $(#[$meta:meta] $e:expr),* }) => { 0 $(+ { let _ = stringify!($meta); 1 })* };
+}
+
+cfg_tt! {
+    pub fn f() -> i32 {
+        count_attrs!(#[cfg_tt::verbatim] { #[cfg(windows)] 1, #[cfg(unix)] 2 }) #[cfg(windows)] (+ 10)
+    }
+}
+
+fn main() {
+    #[cfg(windows)]
+    assert_eq!(f(), 12);
+    #[cfg(not(windows))]
+    assert_eq!(f(), 2);
+}
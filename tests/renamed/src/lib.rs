@@ -0,0 +1,36 @@
+//! `cfg_tt` under a different name, so that `::cfg_tt` doesn't resolve.
+
+// the dependency is renamed in Cargo.toml and re-exported from a facade module
+mod facade {
+    pub use renamed_cfg_tt::*;
+}
+
+facade::cfg_tt! {
+    #![cfg_tt(single_expansion, crate = crate::facade)]
+
+    pub fn f() -> i32 {
+        1 #[cfg(unix)] (+ 1) #[cfg(windows)] (+ 2)
+    }
+}
+
+#[renamed_cfg_tt::cfg_tt_attr(single_expansion, crate = ::renamed_cfg_tt)]
+pub fn g() -> i32 {
+    #[cfg(unix)]
+    {
+        2
+    }
+    #[cfg_else]
+    {
+        3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_through_the_given_path() {
+        assert_eq!(f(), g());
+    }
+}